@@ -0,0 +1,192 @@
+//! The pairing code the plugin needs to talk to us, so other programs and webpages can't push
+//! results or write files through the server.
+
+use crate::cache::{self, CACHE_DIR};
+use anyhow::Context;
+use axum::http::{HeaderMap, header::AUTHORIZATION};
+use fs_err::tokio as fs;
+use log::warn;
+use std::{io, path::Path};
+use tokio::io::AsyncWriteExt;
+
+const TOKEN_FILE: &str = "token";
+
+/// Crockford's base32, which leaves out letters that are easy to mix up.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Two groups of five characters, or 50 bits.
+const TOKEN_LEN: usize = 10;
+
+/// Reads the project's token, making one the first time. It's kept in the project so Studio only
+/// has to be paired once. On Unix, only the current user can read it.
+pub async fn token(project_root: &Path) -> anyhow::Result<String> {
+    let path = project_root.join(CACHE_DIR).join(TOKEN_FILE);
+
+    match fs::read_to_string(&path).await {
+        Ok(token) if is_valid(&token) => return Ok(token.trim().to_string()),
+        Ok(_) => {
+            // An empty or mangled token would let anything in, so start again with a new one
+            warn!(
+                "{} doesn't hold a valid pairing code, so we made a new one. Studio will need to \
+                 be paired again.",
+                path.display()
+            );
+            fs::remove_file(&path).await?;
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let token = generate()?;
+
+    cache::create_dir(project_root).await?;
+
+    // Create it with the right permissions first, so nobody else gets a chance to read it
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&path).await?;
+
+    file.write_all(token.as_bytes()).await?;
+    file.flush().await?;
+
+    Ok(token)
+}
+
+fn generate() -> anyhow::Result<String> {
+    let mut bytes = [0; TOKEN_LEN];
+    getrandom::fill(&mut bytes).context("Failed to generate a pairing code")?;
+
+    let chars: Vec<char> = bytes
+        .iter()
+        .map(|b| ALPHABET[*b as usize % ALPHABET.len()] as char)
+        .collect();
+    let (first, second) = chars.split_at(TOKEN_LEN / 2);
+
+    Ok(format!(
+        "{}-{}",
+        first.iter().collect::<String>(),
+        second.iter().collect::<String>()
+    ))
+}
+
+/// Whether a request carries the token as `Authorization: Bearer <token>`. Case and dashes don't
+/// matter, since people type it in by hand.
+pub fn is_authorized(headers: &HeaderMap, token: &str) -> bool {
+    let Some(given) = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+
+    if !is_valid(token) {
+        return false;
+    }
+
+    let (given, token) = (normalize(given), normalize(token));
+
+    // Compare every byte, so how long this takes doesn't give away how much of it was right
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn is_valid(token: &str) -> bool {
+    normalize(token).len() == TOKEN_LEN
+}
+
+fn normalize(token: &str) -> String {
+    token
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(authorization).unwrap());
+        headers
+    }
+
+    #[test]
+    fn accepts_the_token() {
+        assert!(is_authorized(&headers("Bearer 0123A-BCDEF"), "0123A-BCDEF"));
+    }
+
+    #[test]
+    fn ignores_case_and_dashes() {
+        assert!(is_authorized(&headers("Bearer 0123abcdef"), "0123A-BCDEF"));
+        assert!(is_authorized(
+            &headers("Bearer 0123a-bcd-ef"),
+            "0123A-BCDEF"
+        ));
+    }
+
+    #[test]
+    fn rejects_other_tokens() {
+        let token = "0123A-BCDEF";
+
+        assert!(!is_authorized(&HeaderMap::new(), token));
+        assert!(!is_authorized(&headers("0123A-BCDEF"), token));
+        assert!(!is_authorized(&headers("Bearer 0123A-BCDEG"), token));
+        assert!(!is_authorized(&headers("Bearer 0123A-BCDE"), token));
+        assert!(!is_authorized(&headers("Bearer "), token));
+    }
+
+    #[test]
+    fn rejects_everything_without_a_valid_token() {
+        for token in ["", "-", "0123A"] {
+            assert!(!is_authorized(&headers("Bearer "), token), "{token:?}");
+            assert!(!is_authorized(&headers(&format!("Bearer {token}")), token));
+        }
+    }
+
+    #[tokio::test]
+    async fn makes_a_token_once() {
+        let root = tempfile::tempdir().unwrap();
+
+        let token = token(root.path()).await.unwrap();
+        assert!(is_valid(&token));
+        assert_eq!(super::token(root.path()).await.unwrap(), token);
+
+        let dir = root.path().join(CACHE_DIR);
+        assert_eq!(
+            std::fs::read_to_string(dir.join(".gitignore")).unwrap(),
+            "*\n"
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.join(TOKEN_FILE))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn replaces_an_invalid_token() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join(CACHE_DIR).join(TOKEN_FILE);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "").unwrap();
+
+        let token = token(root.path()).await.unwrap();
+        assert!(is_valid(&token));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), token);
+    }
+}
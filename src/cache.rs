@@ -0,0 +1,79 @@
+//! What we remember between runs, kept in `.jest-companion` in the project root.
+
+use crate::output::Output;
+use fs_err::tokio as fs;
+use serde::{Serialize, de::DeserializeOwned};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+pub const CACHE_DIR: &str = ".jest-companion";
+const DURATIONS_FILE: &str = "durations.json";
+const FAILURES_FILE: &str = "last-failed.json";
+
+/// How long each test file took to run last time, in milliseconds, by instance path.
+pub type Durations = HashMap<String, u64>;
+
+pub async fn durations(project_root: &Path) -> Durations {
+    load(project_root, DURATIONS_FILE).await
+}
+
+/// Remembers how long each test file in `output` took, keeping what we knew about the others.
+pub async fn record_durations(project_root: &Path, output: &Output) -> anyhow::Result<()> {
+    let mut durations = durations(project_root).await;
+
+    for (path, runtime) in output.file_runtimes() {
+        durations.insert(path.to_string(), runtime);
+    }
+
+    save(project_root, DURATIONS_FILE, &durations).await
+}
+
+/// The full names of the tests that failed last time, by the instance path of their file. Files
+/// that failed to run at all have no names.
+pub type Failures = BTreeMap<String, Vec<String>>;
+
+pub async fn failures(project_root: &Path) -> Failures {
+    load(project_root, FAILURES_FILE).await
+}
+
+/// Remembers which tests failed in `output`. Tests that didn't run keep whatever we knew about
+/// them, so running a few tests doesn't forget about failures elsewhere.
+pub async fn record_failures(project_root: &Path, output: &Output) -> anyhow::Result<()> {
+    let mut failures = failures(project_root).await;
+    output.update_failures(&mut failures);
+
+    save(project_root, FAILURES_FILE, &failures).await
+}
+
+// A missing or outdated cache file isn't worth failing over, we just start from scratch
+async fn load<T: DeserializeOwned + Default>(project_root: &Path, name: &str) -> T {
+    let path = project_root.join(CACHE_DIR).join(name);
+
+    match fs::read_to_string(&path).await {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_default(),
+        Err(_) => T::default(),
+    }
+}
+
+async fn save<T: Serialize>(project_root: &Path, name: &str, value: &T) -> anyhow::Result<()> {
+    let dir = create_dir(project_root).await?;
+    fs::write(dir.join(name), serde_json::to_string_pretty(value)?).await?;
+
+    Ok(())
+}
+
+/// Makes the cache directory if it isn't there yet, with a `.gitignore` so nothing in it gets
+/// committed by accident.
+pub async fn create_dir(project_root: &Path) -> anyhow::Result<PathBuf> {
+    let dir = project_root.join(CACHE_DIR);
+    fs::create_dir_all(&dir).await?;
+
+    let gitignore = dir.join(".gitignore");
+    if fs::metadata(&gitignore).await.is_err() {
+        fs::write(&gitignore, "*\n").await?;
+    }
+
+    Ok(dir)
+}
@@ -0,0 +1,80 @@
+use crate::{config::Config, pattern, resolver::dir_instance_path};
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Asks git which files changed, relative to `project_root`. Without `since`, that's whatever
+/// hasn't been committed yet. With it, it's also everything committed since the branch split off
+/// from `since`.
+pub async fn changed_files(
+    project_root: &Path,
+    since: Option<&str>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let base = match since {
+        Some(since) => git(project_root, &["merge-base", since, "HEAD"]).await?,
+        None => "HEAD".to_string(),
+    };
+
+    // `-z` gives us paths as they are, rather than quoting any with unusual characters
+    let changed = git(
+        project_root,
+        &["diff", "--name-only", "--relative", "-z", base.trim()],
+    )
+    .await?;
+    let untracked = git(
+        project_root,
+        &["ls-files", "--others", "--exclude-standard", "-z"],
+    )
+    .await?;
+
+    Ok(changed
+        .split('\0')
+        .chain(untracked.split('\0'))
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// A `testPathPattern` for the tests in the directories these files are in, or `None` if none of
+/// them are in a project.
+pub fn test_path_pattern(
+    config: &Config,
+    project_root: &Path,
+    files: &[PathBuf],
+) -> Option<String> {
+    let mut dirs: Vec<String> = files
+        .iter()
+        .filter_map(|file| {
+            let dir = project_root.join(file.parent()?);
+            dir_instance_path(config, &dir, project_root)
+        })
+        .collect();
+
+    if dirs.is_empty() {
+        return None;
+    }
+
+    dirs.sort();
+    dirs.dedup();
+
+    Some(format!("{}/", pattern::any_of(dirs)))
+}
+
+async fn git(project_root: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(project_root)
+        .output()
+        .await
+        .context("Failed to run git. Is it installed?")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
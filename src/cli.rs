@@ -1,12 +1,70 @@
 use std::path::PathBuf;
 
-use clap::{Args, Parser, command};
-use serde::Serialize;
+use crate::config::Config;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Parser, Serialize, Clone)]
-#[command(version, about = "Run jest-lua tests from the command line")]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Parser, Clone)]
+#[command(
+    version,
+    about = "Run jest-lua tests from the command line",
+    args_conflicts_with_subcommands = true
+)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum Command {
+    /// Start a server that stays connected to Studio, and runs tests whenever `jest-companion run` asks it to.
+    Serve(ServeArgs),
+    /// Run tests through a server started with `jest-companion serve`.
+    Run(Box<RunArgs>),
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct ServeArgs {
+    /// The path to run jest-companion in. Defaults to the current directory.
+    #[arg(default_value = ".")]
+    pub path: PathBuf,
+
+    #[command(flatten)]
+    pub server: ServerArgs,
+}
+
+#[derive(Debug, Args, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerArgs {
+    /// The address the server listens on. Overrides `host` in jest-companion.toml.
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// The port the server listens on. Overrides `port` in jest-companion.toml.
+    #[arg(short, long)]
+    pub port: Option<u16>,
+}
+
+impl ServerArgs {
+    pub fn address(&self, config: &Config) -> String {
+        let host = self.host.as_deref().unwrap_or(&config.host);
+        let port = self.port.unwrap_or(config.port);
+
+        // IPv6 addresses need brackets, so their colons aren't mistaken for the port
+        if host.contains(':') && !host.starts_with('[') {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        }
+    }
+}
+
+#[derive(Debug, Args, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RunArgs {
     /// The path to run jest-companion in. Defaults to the current directory.
     #[arg(default_value = ".")]
     pub path: PathBuf,
@@ -15,14 +73,128 @@ pub struct Cli {
     #[arg(short, long, default_value_t = 30)]
     pub server_timeout: u64,
 
+    #[command(flatten)]
+    pub server: ServerArgs,
+
+    /// Keep running and re-run tests whenever files in the project directories change.
+    #[arg(short, long)]
+    pub watch: bool,
+
+    /// Reporters to use for the results.
+    #[arg(
+        long = "reporter",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "default"
+    )]
+    pub reporters: Vec<Reporter>,
+
+    /// Print the results as JSON. Shorthand for `--reporter json`.
+    #[arg(long, conflicts_with = "reporters")]
+    pub json: bool,
+
+    /// File to write the report to, for reporters that produce a file. Defaults to standard output.
+    #[arg(long, alias = "outputFile")]
+    pub output_file: Option<PathBuf>,
+
+    /// Only run in the Studio with this place open, given as a place name, place ID or game ID.
+    #[arg(long)]
+    pub place: Option<String>,
+
+    /// Split the test files into this many runs, so several open Studio instances can share the
+    /// work. Files are split by how long they took last time.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub shards: Option<u32>,
+
+    /// Run failed tests again up to this many times. Tests that pass on a retry are reported as
+    /// flaky, and don't fail the run.
+    #[arg(long, value_name = "N")]
+    pub retries: Option<u32>,
+
+    /// Only run the tests that failed last time.
+    #[arg(
+        long,
+        visible_alias = "last-failed",
+        alias = "onlyFailures",
+        conflicts_with_all = ["test_path_pattern", "test_name_pattern"]
+    )]
+    pub only_failures: bool,
+
+    /// Only run tests next to files that haven't been committed yet, according to git.
+    #[arg(
+        long,
+        alias = "onlyChanged",
+        conflicts_with_all = ["test_path_pattern", "only_failures", "changed_since"]
+    )]
+    pub only_changed: bool,
+
+    /// Only run tests next to files that changed since this branch or commit, including ones that
+    /// haven't been committed yet.
+    #[arg(
+        long,
+        value_name = "REF",
+        alias = "changedSince",
+        conflicts_with_all = ["test_path_pattern", "only_failures"]
+    )]
+    pub changed_since: Option<String>,
+
     #[command(flatten, next_help_heading = "runCLI options")]
     pub options: JestOptions,
 }
 
-#[derive(Debug, Args, Serialize, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Reporter {
+    /// Human-readable results in the terminal.
+    Default,
+    /// JUnit XML.
+    Junit,
+    /// The full results as JSON.
+    Json,
+    /// GitHub Actions annotations for each failed test.
+    Github,
+}
+
+impl Reporter {
+    /// Whether the reporter produces a report that can be written to `--output-file`.
+    pub fn produces_file(self) -> bool {
+        matches!(self, Reporter::Junit | Reporter::Json)
+    }
+}
+
+impl RunArgs {
+    pub fn reporters(&self) -> Vec<Reporter> {
+        if self.json {
+            vec![Reporter::Json]
+        } else {
+            self.reporters.clone()
+        }
+    }
+}
+
+#[derive(Debug, Args, Serialize, Deserialize, Clone)]
 #[command(rename_all = "camelCase")]
 #[serde(rename_all = "camelCase")]
 pub struct JestOptions {
+    /// Stop running tests after n test files fail, like --bail=2. Defaults to 1 when no number is given.
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1"
+    )]
+    pub bail: Option<u32>,
+
+    /// Fail on missing or obsolete snapshots instead of writing them, like you'd want in CI.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub ci: Option<bool>,
+
     /// Automatically clear mock calls, instances, contexts and results before every test.
     /// Equivalent to calling jest.clearAllMocks() before each test. This does not remove any mock implementation that may have been provided.
     #[arg(long, verbatim_doc_comment)]
@@ -51,24 +223,28 @@ pub struct JestOptions {
     #[arg(long, verbatim_doc_comment)]
     reset_mocks: Option<bool>,
 
+    /// Prevent tests from printing messages through the console.
+    #[arg(long)]
+    pub silent: Option<bool>,
+
     /// The glob patterns Jest uses to detect test files.
     #[arg(long, value_delimiter = ',')]
-    test_match: Option<Vec<String>>,
+    pub test_match: Option<Vec<String>>,
 
     /// Run only tests with a name that matches the regex.
     /// For example, suppose you want to run only tests related to authorization which will have names like "GET /api/posts with auth", then you can use testNamePattern = "auth".
     /// The regex is matched against the full name, which is a combination of the test name and all its surrounding describe blocks.
     #[arg(long, verbatim_doc_comment)]
-    test_name_pattern: Option<String>,
+    pub test_name_pattern: Option<String>,
 
     /// An array of regexp pattern strings that are tested against all tests paths before executing the test.
     /// Contrary to testPathPattern, it will only run those tests with a path that does not match with the provided regexp expressions.
     #[arg(long, verbatim_doc_comment)]
-    test_path_ignore_patterns: Option<Vec<String>>,
+    pub test_path_ignore_patterns: Option<Vec<String>>,
 
     /// A regexp pattern string that is matched against all tests paths before executing the test.
     #[arg(long)]
-    test_path_pattern: Option<Option<String>>,
+    pub test_path_pattern: Option<Option<String>>,
 
     /// Default timeout of a test in milliseconds.
     #[arg(long)]
@@ -0,0 +1,82 @@
+use crate::server::{AppState, Event, RunRequest};
+use anyhow::Context;
+use log::warn;
+use tokio::sync::mpsc;
+
+/// Where runs are sent: the server in this process, or one started with `jest-companion serve`.
+pub enum Runner {
+    Local(AppState),
+    Remote {
+        url: String,
+        token: String,
+        http: reqwest::Client,
+    },
+}
+
+impl Runner {
+    pub fn remote(address: &str, token: String) -> Self {
+        Self::Remote {
+            url: format!("http://{address}"),
+            token,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Starts a run, returning the events that happen to it. Dropping the receiver cancels the run.
+    pub async fn submit(
+        &self,
+        request: RunRequest,
+    ) -> anyhow::Result<mpsc::UnboundedReceiver<Event>> {
+        let (url, token, http) = match self {
+            Self::Local(state) => return Ok(state.submit(request).await),
+            Self::Remote { url, token, http } => (url, token, http),
+        };
+
+        let response = http
+            .post(format!("{url}/run"))
+            .bearer_auth(token)
+            .json(&request)
+            .send()
+            .await
+            .context("Failed to reach the server. Is `jest-companion serve` running?")?;
+
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            anyhow::bail!(
+                "The server didn't accept this project's pairing code. Is it serving a different project?"
+            );
+        }
+        let mut response = response.error_for_status()?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut buf = Vec::new();
+
+            loop {
+                let chunk = tokio::select! {
+                    chunk = response.chunk() => chunk,
+                    // Dropping the response closes the connection, which cancels the run
+                    _ = tx.closed() => return,
+                };
+
+                let Ok(Some(chunk)) = chunk else {
+                    return;
+                };
+                buf.extend_from_slice(&chunk);
+
+                while let Some(i) = buf.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buf.drain(..=i).collect();
+
+                    match serde_json::from_slice(&line) {
+                        Ok(event) => {
+                            tx.send(event).ok();
+                        }
+                        Err(e) => warn!("Server sent an invalid event: {e}"),
+                    }
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+}
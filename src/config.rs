@@ -1,7 +1,134 @@
+use crate::rojo;
+use anyhow::Context;
+use fs_err::tokio as fs;
 use serde::Deserialize;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// The port the plugin connects to unless it's told otherwise.
+pub const DEFAULT_PORT: u16 = 28860;
+
+/// Where Wally puts dependencies, which come with their own tests.
+const PACKAGE_DIRS: &[&str] = &["Packages", "DevPackages", "ServerPackages"];
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    pub projects: HashMap<String, PathBuf>,
+    /// The instance paths jest-lua runs tests in. Worked out from the mappings if left out.
+    #[serde(default)]
+    projects: Projects,
+
+    /// Where each instance path lives on disk. Read from the Rojo project if left out.
+    #[serde(default)]
+    pub mappings: HashMap<String, PathBuf>,
+
+    /// The Rojo project to read `mappings` from, relative to the project root.
+    #[serde(default = "default_rojo_project")]
+    pub rojo_project: PathBuf,
+
+    /// The address the server listens on.
+    #[serde(default = "default_host")]
+    pub host: String,
+
+    /// The port the server listens on. The plugin needs to be told about it if it isn't the default.
+    #[serde(default = "default_port")]
+    pub port: u16,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Projects {
+    List(Vec<String>),
+    /// Older configs mapped each project to its directory, before there were separate mappings.
+    Table(HashMap<String, PathBuf>),
+}
+
+impl Default for Projects {
+    fn default() -> Self {
+        Projects::List(Vec::new())
+    }
+}
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_rojo_project() -> PathBuf {
+    PathBuf::from(rojo::DEFAULT_PROJECT)
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+impl Config {
+    pub async fn load(project_root: &Path) -> anyhow::Result<Self> {
+        let config = fs::read_to_string(project_root.join("jest-companion.toml")).await?;
+        let mut config: Config = toml::from_str(&config).context("Failed to parse config file")?;
+
+        if let Projects::Table(table) = &config.projects {
+            for (project, path) in table {
+                config
+                    .mappings
+                    .entry(project.clone())
+                    .or_insert_with(|| path.clone());
+            }
+            config.projects = Projects::List(table.keys().cloned().collect());
+        }
+
+        if config.mappings.is_empty() {
+            config.mappings =
+                rojo::mappings(project_root, &config.rojo_project).with_context(|| {
+                    format!(
+                        "No mappings in jest-companion.toml, and couldn't read them from {}",
+                        config.rojo_project.display()
+                    )
+                })?;
+        }
+
+        if matches!(&config.projects, Projects::List(list) if list.is_empty()) {
+            let projects = config.default_projects(project_root);
+            if projects.is_empty() {
+                anyhow::bail!(
+                    "Couldn't work out where your tests are. List them as `projects` in jest-companion.toml."
+                );
+            }
+
+            config.projects = Projects::List(projects);
+        }
+
+        Ok(config)
+    }
+
+    /// The mappings worth running tests in when `projects` isn't set. Ones inside other mappings
+    /// would run their tests twice, missing ones can't be found in Studio, and packages aren't ours.
+    fn default_projects(&self, project_root: &Path) -> Vec<String> {
+        let mut projects: Vec<String> = self
+            .mappings
+            .iter()
+            .filter(|(key, path)| {
+                let nested = self.mappings.keys().any(|other| {
+                    key.strip_prefix(other.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+                });
+                let package = key.split('/').any(|name| PACKAGE_DIRS.contains(&name));
+
+                !nested && !package && project_root.join(path).exists()
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        projects.sort();
+        projects
+    }
+
+    /// The instance paths jest-lua runs tests in.
+    pub fn projects(&self) -> &[String] {
+        match &self.projects {
+            Projects::List(list) => list,
+            // Turned into a list when the config is loaded
+            Projects::Table(_) => &[],
+        }
+    }
 }
@@ -1,38 +1,44 @@
 use crate::{
-    cli::{Cli, JestOptions},
+    cache::Failures,
+    cli::{Cli, Command, JestOptions, Reporter, RunArgs, ServeArgs},
+    client::Runner,
     config::Config,
-    output::Output,
-    resolver::resolve_path,
-};
-use anyhow::Context;
-use axum::{
-    Json, Router,
-    extract::{DefaultBodyLimit, Path as AxumPath, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{delete, get, post, put},
+    output::{Output, TestResult},
+    server::{AppState, Event, RunRequest},
+    watch::FileWatcher,
 };
 use clap::Parser;
 use fs_err::tokio as fs;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use indicatif_log_bridge::LogWrapper;
 use log::{error, info, warn};
-use serde::{Deserialize, Serialize};
-use std::{sync::Arc, time::Duration};
-use tokio::sync::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use tokio_stream::{StreamExt, StreamMap, wrappers::UnboundedReceiverStream};
 
+mod auth;
+mod cache;
+mod changed;
 mod cli;
+mod client;
 mod config;
 mod output;
+mod pattern;
 mod resolver;
+mod rojo;
+mod server;
+mod shard;
+mod stack_trace;
+mod watch;
 
-#[derive(Debug, Clone)]
-struct AppState {
-    args: Arc<Cli>,
-    config: Arc<Config>,
-    spinner: Arc<Mutex<ProgressBar>>,
-    plugin_connected: Arc<Mutex<bool>>,
-}
+/// What shells use for a process stopped by Ctrl+C.
+const EXIT_CANCELLED: i32 = 130;
+
+/// How long to wait for the plugin to stop running tests after Ctrl+C.
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -48,211 +54,422 @@ async fn main() -> anyhow::Result<()> {
     LogWrapper::new(multi.clone(), logger).try_init().unwrap();
     log::set_max_level(level);
 
-    let args = Cli::parse();
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::Serve(args)) => serve(args).await,
+        Some(Command::Run(args)) => run(*args, multi, false).await,
+        None => run(cli.run, multi, true).await,
+    }
+}
+
+async fn serve(args: ServeArgs) -> anyhow::Result<()> {
+    let config = Config::load(&args.path).await?;
+    let address = args.server.address(&config);
+    let token = auth::token(&args.path).await?;
+    let state = AppState::new(Arc::new(config), args.path, token);
+
+    let listener = server::bind(&address).await?;
+    info!("Listening on {address}. Use `jest-companion run` to run tests.");
+
+    axum::serve(listener, server::router(state)).await?;
+
+    Ok(())
+}
+
+/// Runs tests and exits. With `local`, we host the server ourselves for the duration of the run,
+/// otherwise runs are sent to `jest-companion serve`.
+async fn run(args: RunArgs, multi: MultiProgress, local: bool) -> anyhow::Result<()> {
+    let file_reporters = args.reporters().into_iter().filter(|r| r.produces_file());
+    if args.output_file.is_some() && file_reporters.count() > 1 {
+        anyhow::bail!("Only one reporter can write to --output-file");
+    }
+
+    let config = Arc::new(Config::load(&args.path).await?);
+    let address = args.server.address(&config);
+
+    let watcher = if args.watch {
+        Some(FileWatcher::new(&config, &args.path)?)
+    } else {
+        None
+    };
+
+    let token = auth::token(&args.path).await?;
+
+    if !local {
+        let runner = Runner::remote(&address, token);
+        std::process::exit(drive(&args, &config, &multi, &runner, watcher).await);
+    }
+
+    let state = AppState::new(config.clone(), args.path.clone(), token);
+    let runner = Runner::Local(state.clone());
+
+    let listener = server::bind(&address).await?;
 
-    let config = fs::read_to_string(args.path.join("jest-companion.toml")).await?;
-    let config: Config = toml::from_str(&config).context("Failed to parse config file")?;
+    tokio::select! {
+        res = axum::serve(listener, server::router(state)) => res?,
+        code = drive(&args, &config, &multi, &runner, watcher) => {
+            // Give the server a moment to respond to the plugin before we exit
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            std::process::exit(code);
+        }
+    }
+
+    Ok(())
+}
 
+fn new_spinner(multi: &MultiProgress) -> ProgressBar {
     let spinner = multi.add(ProgressBar::new_spinner());
     spinner.set_style(ProgressStyle::default_spinner());
     spinner.set_message("Waiting for plugin");
     spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
 
-    let state = AppState {
-        args: Arc::new(args),
-        config: Arc::new(config),
-        spinner: Arc::new(Mutex::new(spinner)),
-        plugin_connected: Arc::new(Mutex::new(false)),
-    };
+/// Runs the tests and returns the exit code. With a watcher, this keeps running the tests whenever
+/// a file changes instead.
+async fn drive(
+    args: &RunArgs,
+    config: &Config,
+    multi: &MultiProgress,
+    runner: &Runner,
+    mut watcher: Option<FileWatcher>,
+) -> i32 {
+    loop {
+        let success = match run_once(args, config, multi, runner).await {
+            Ok(Some(success)) => success,
+            Ok(None) => return cancel(runner).await,
+            Err(e) => {
+                error!("{e:#}");
+                false
+            }
+        };
 
-    let app = Router::new()
-        .route("/output", post(output))
-        .route("/poll", post(poll))
-        .route("/run-error", post(run_error))
-        .route("/fs/file/{*path}", put(fs_write))
-        .route("/fs/dir/{*path}", put(fs_create_dir_all))
-        .route("/fs/exists/{*path}", get(fs_exists))
-        .route("/fs/file/{*path}", delete(fs_delete))
-        .with_state(state.clone())
-        .layer(DefaultBodyLimit::max(1024 * 1024 * 1024));
-
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:28860").await?;
-
-    {
-        let state = state.clone();
-        tokio::spawn(async move {
-            tokio::time::sleep(Duration::from_secs(state.args.server_timeout)).await;
-
-            let spinner = state.spinner.lock().await;
-            spinner.finish_and_clear();
-
-            error!("No places have reported anything. Studio might not be open?");
-            std::process::exit(1);
-        })
-    };
+        let Some(watcher) = &mut watcher else {
+            return if success { 0 } else { 1 };
+        };
 
-    axum::serve(listener, app).await?;
+        info!("Waiting for file changes...");
 
-    Ok(())
-}
+        let path = tokio::select! {
+            path = watcher.changed() => path,
+            _ = tokio::signal::ctrl_c() => return EXIT_CANCELLED,
+        };
 
-const PROTOCOL_VERSION: &str = "1";
+        let Some(path) = path else {
+            return 1;
+        };
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct PollRequestBody {
-    protocol_version: String,
-    rojo_connected: bool,
+        info!("{} changed, re-running tests", path.display());
+    }
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-struct PollResponseBody {
-    projects: Vec<String>,
-    options: JestOptions,
+/// Stops the run that was cancelled with Ctrl+C, returning the exit code.
+async fn cancel(runner: &Runner) -> i32 {
+    // Our server goes away when we exit, so give the plugin a chance to hear about it first.
+    // Remote servers notice the connection closing instead.
+    if let Runner::Local(state) = runner {
+        state.wait_until_idle(CANCEL_TIMEOUT).await;
+    }
+
+    EXIT_CANCELLED
 }
 
-async fn poll(
-    State(state): State<AppState>,
-    Json(body): Json<PollRequestBody>,
-) -> impl IntoResponse {
-    let mut plugin_connected = state.plugin_connected.lock().await;
-    if *plugin_connected {
-        warn!("A plugin tried to connect while we are already listening to one.");
-        return (StatusCode::BAD_REQUEST, "Already connected").into_response();
-    }
+/// Runs the tests once and reports the results, returning whether they passed, or `None` if the
+/// run was cancelled with Ctrl+C.
+async fn run_once(
+    args: &RunArgs,
+    config: &Config,
+    multi: &MultiProgress,
+    runner: &Runner,
+) -> anyhow::Result<Option<bool>> {
+    let mut options = args.options.clone();
+
+    if args.only_failures {
+        let failures = cache::failures(&args.path).await;
+        if failures.is_empty() {
+            info!("No tests failed last time, so there's nothing to run");
+            return Ok(Some(true));
+        }
 
-    if body.protocol_version != PROTOCOL_VERSION {
-        warn!(
-            "The plugin tried to connect with protocol version {} but we are expecting {PROTOCOL_VERSION}. Make sure your versions align.",
-            body.protocol_version
-        );
-
-        return (
-            StatusCode::BAD_REQUEST,
-            format!(
-                "Incorrect protocol version: expected {PROTOCOL_VERSION}, got {}",
-                body.protocol_version
-            ),
-        )
-            .into_response();
+        only_failures(&mut options, &failures);
     }
 
-    *plugin_connected = true;
+    if args.only_changed || args.changed_since.is_some() {
+        let files = changed::changed_files(&args.path, args.changed_since.as_deref()).await?;
 
-    if !body.rojo_connected {
-        warn!("Rojo is not connected on the running Studio instance");
-    }
+        let Some(pattern) = changed::test_path_pattern(config, &args.path, &files) else {
+            info!("No files in your projects changed, so there's nothing to run");
+            return Ok(Some(true));
+        };
 
-    let spinner = state.spinner.lock().await;
-    spinner.set_message("Waiting for test results");
+        options.test_path_pattern = Some(Some(pattern));
+    }
 
-    let projects: Vec<String> = state.config.projects.keys().cloned().collect();
+    let mut streamed = HashSet::new();
 
-    let body = PollResponseBody {
-        projects,
-        options: state.args.options.clone(),
+    let mut output = match execute(args, config, multi, runner, &options, &mut streamed).await? {
+        Outcome::Finished(output) => output,
+        Outcome::Failed(msg) => {
+            error!("{msg}");
+            return Ok(Some(false));
+        }
+        Outcome::Cancelled => return Ok(None),
     };
 
-    (StatusCode::OK, Json(body)).into_response()
-}
+    let retries = args.retries.unwrap_or_default();
+    for attempt in 1..=retries {
+        let mut failures = Failures::new();
+        output.update_failures(&mut failures);
 
-async fn output(State(state): State<AppState>, Json(output): Json<Output>) -> impl IntoResponse {
-    let spinner = state.spinner.lock().await;
-    spinner.finish_and_clear();
+        if failures.is_empty() || output.was_interrupted() {
+            break;
+        }
 
-    let formatter = output::Formatter::new(state.args.options.verbose.unwrap_or_default());
-    let text = formatter.format_output(&output);
+        info!("Retrying failed tests ({attempt}/{retries})");
 
-    print!("{}", text);
+        let mut retry_options = options.clone();
+        only_failures(&mut retry_options, &failures);
 
-    tokio::spawn(async move {
-        tokio::time::sleep(Duration::from_millis(100)).await;
-        std::process::exit(if output.was_successful() { 0 } else { 1 });
-    });
+        match execute(args, config, multi, runner, &retry_options, &mut streamed).await? {
+            Outcome::Finished(retry) => output.apply_retry(retry),
+            // We still have the results from before, so report those
+            Outcome::Failed(msg) => {
+                error!("{msg}");
+                break;
+            }
+            Outcome::Cancelled => return Ok(None),
+        }
+    }
 
-    (StatusCode::OK, ())
-}
+    if let Err(e) = cache::record_durations(&args.path, &output).await {
+        warn!("Failed to remember how long tests took: {e:#}");
+    }
+    if let Err(e) = cache::record_failures(&args.path, &output).await {
+        warn!("Failed to remember which tests failed: {e:#}");
+    }
 
-async fn run_error(State(state): State<AppState>) -> impl IntoResponse {
-    let spinner = state.spinner.lock().await;
-    spinner.finish_and_clear();
+    report(args, config, &output, &streamed).await?;
+    Ok(Some(output.was_successful()))
+}
 
-    error!("The test runner encountered an error. See the Studio output for more details.");
+/// How a run in Studio ended.
+enum Outcome {
+    Finished(Output),
+    Failed(&'static str),
+    Cancelled,
+}
 
-    tokio::spawn(async move {
-        tokio::time::sleep(Duration::from_millis(100)).await;
-        std::process::exit(1);
-    });
+/// Sends a run to Studio and waits for its results, printing test files as they finish. Their
+/// paths are added to `streamed`.
+async fn execute(
+    args: &RunArgs,
+    config: &Config,
+    multi: &MultiProgress,
+    runner: &Runner,
+    options: &JestOptions,
+    streamed: &mut HashSet<String>,
+) -> anyhow::Result<Outcome> {
+    // Each shard is its own run, which whichever Studio is free picks up
+    let shards = shard::split(args, options.clone(), config).await?;
+    let sharded = shards.len() > 1;
+
+    let mut events = StreamMap::new();
+    for (i, options) in shards.into_iter().enumerate() {
+        let rx = runner
+            .submit(RunRequest {
+                options,
+                place: args.place.clone(),
+                sharded,
+            })
+            .await?;
+        events.insert(i, UnboundedReceiverStream::new(rx));
+    }
+    let shards = events.len();
+
+    let spinner = new_spinner(multi);
+    let timeout = Duration::from_secs(args.server_timeout);
+    let mut places = Vec::new();
+    let mut unpaired = false;
+    let mut finished_suites = 0;
+    let mut total_suites = HashMap::new();
+    let mut failed_suites = 0;
+    let mut suites: HashMap<usize, Vec<TestResult>> = HashMap::new();
+    let mut outputs = BTreeMap::new();
+
+    // Test files are printed as they finish, unless the results only go to other reporters
+    let stream = args.reporters().contains(&Reporter::Default);
+    let formatter = formatter(args, config);
+
+    let outcome = loop {
+        let event = tokio::select! {
+            event = tokio::time::timeout(timeout, events.next()) => event,
+            _ = tokio::signal::ctrl_c() => break Outcome::Cancelled,
+        };
+
+        match event {
+            Ok(Some((_, Event::Started { place }))) => {
+                places.push(place);
+                spinner.set_message(format!(
+                    "Waiting for test results from {}",
+                    places.join(", ")
+                ));
+            }
+            // The plugin keeps asking while it waits to be paired, so this doesn't time out either
+            Ok(Some((_, Event::Unpaired { place }))) => {
+                unpaired = true;
+                if places.is_empty() {
+                    spinner.set_message(format!("Waiting for pairing with {place}"));
+                }
+            }
+            Ok(Some((shard, Event::SuiteResult(result)))) => {
+                if stream {
+                    let text = formatter.format_test_file(&result.test_result);
+                    multi.suspend(|| print!("{text}"));
+                }
 
-    (StatusCode::OK, ())
-}
+                streamed.insert(result.test_result.test_file_path().to_string());
+                finished_suites += 1;
+                total_suites.insert(shard, result.num_total_test_suites);
+                spinner.set_message(format!(
+                    "Running tests ({finished_suites}/{} suites)",
+                    total_suites.values().sum::<u32>()
+                ));
 
-async fn fs_write(
-    State(state): State<AppState>,
-    AxumPath(virtual_path): AxumPath<String>,
-    body: String,
-) -> impl IntoResponse {
-    match resolve_path(&state.config, &virtual_path, &state.args.path) {
-        Some(real_path) => {
-            if let Some(parent) = real_path.parent()
-                && let Err(e) = fs::create_dir_all(parent).await
-            {
-                return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+                if result.test_result.failed() {
+                    failed_suites += 1;
+                }
+                suites.entry(shard).or_default().push(result.test_result);
+
+                // Each shard only knows about its own failures, so bailing across them is up to us.
+                // Dropping the other shards' events when we return cancels them.
+                if sharded
+                    && options
+                        .bail
+                        .is_some_and(|bail| bail > 0 && failed_suites >= bail)
+                {
+                    info!("Stopping the run early because of --bail");
+                    let output = interrupted(shards, outputs, suites, &total_suites);
+                    break Outcome::Finished(output);
+                }
             }
-            match fs::write(&real_path, body).await {
-                Ok(_) => {
-                    info!("File written: {}", real_path.display());
-                    (StatusCode::OK, ()).into_response()
+            Ok(Some((_, Event::Logs(logs)))) => {
+                if stream {
+                    let text = formatter.format_logs(&logs);
+                    multi.suspend(|| print!("{text}"));
                 }
-                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            }
+            Ok(Some((shard, Event::Output(output)))) => {
+                outputs.insert(shard, output);
+                if outputs.len() == shards {
+                    break Outcome::Finished(Output::merge(outputs.into_values().collect()));
+                }
+            }
+            Ok(Some((_, Event::RunError))) => {
+                break Outcome::Failed(
+                    "The test runner encountered an error. See the Studio output for more details.",
+                );
+            }
+            Ok(None) => break Outcome::Failed("Lost connection to the server"),
+            Err(_) if !places.is_empty() => {
+                break Outcome::Failed("Timed out waiting for test results");
+            }
+            Err(_) if unpaired => {
+                break Outcome::Failed(
+                    "Studio stopped asking for tests before it was paired. Is the plugin still open?",
+                );
+            }
+            Err(_) if args.place.is_some() => {
+                break Outcome::Failed(
+                    "No Studio with that place has reported anything. Is it open?",
+                );
+            }
+            Err(_) => {
+                break Outcome::Failed(
+                    "No places have reported anything. Studio might not be open?",
+                );
             }
         }
-        None => (StatusCode::NOT_FOUND, "Could not resolve path").into_response(),
-    }
+    };
+
+    spinner.finish_and_clear();
+
+    Ok(outcome)
 }
 
-async fn fs_create_dir_all(
-    State(state): State<AppState>,
-    AxumPath(virtual_path): AxumPath<String>,
-) -> impl IntoResponse {
-    match resolve_path(&state.config, &virtual_path, &state.args.path) {
-        Some(real_path) => match fs::create_dir_all(&real_path).await {
-            Ok(_) => {
-                info!("Directory created: {}", real_path.display());
-                (StatusCode::OK, ()).into_response()
-            }
-            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-        },
-        None => (StatusCode::NOT_FOUND, "Could not resolve path").into_response(),
-    }
+/// Combines the results of a sharded run that was stopped early. Shards that hadn't finished yet
+/// only have the test files they sent as they went.
+fn interrupted(
+    shards: usize,
+    mut outputs: BTreeMap<usize, Output>,
+    mut suites: HashMap<usize, Vec<TestResult>>,
+    total_suites: &HashMap<usize, u32>,
+) -> Output {
+    let outputs = (0..shards)
+        .map(|shard| {
+            outputs.remove(&shard).unwrap_or_else(|| {
+                Output::interrupted(
+                    suites.remove(&shard).unwrap_or_default(),
+                    total_suites.get(&shard).copied().unwrap_or_default(),
+                )
+            })
+        })
+        .collect();
+
+    Output::merge(outputs)
 }
 
-async fn fs_exists(
-    State(state): State<AppState>,
-    AxumPath(virtual_path): AxumPath<String>,
-) -> impl IntoResponse {
-    match resolve_path(&state.config, &virtual_path, &state.args.path) {
-        Some(real_path) => match fs::metadata(&real_path).await {
-            Ok(_) => (StatusCode::OK, ()).into_response(),
-            Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
-        },
-        None => (StatusCode::NOT_FOUND, "Could not resolve path").into_response(),
+/// Narrows a run down to the tests that failed last time.
+fn only_failures(options: &mut JestOptions, failures: &Failures) {
+    options.test_path_pattern = Some(Some(pattern::test_paths(failures.keys())));
+
+    // Files that failed to run need all of their tests run again
+    if failures.values().all(|names| !names.is_empty()) {
+        let names = failures.values().flatten();
+        options.test_name_pattern = Some(format!("^{}$", pattern::any_of(names)));
     }
 }
 
-async fn fs_delete(
-    State(state): State<AppState>,
-    AxumPath(virtual_path): AxumPath<String>,
-) -> impl IntoResponse {
-    match resolve_path(&state.config, &virtual_path, &state.args.path) {
-        Some(real_path) => match fs::remove_file(&real_path).await {
-            Ok(_) => {
-                info!("File deleted: {}", real_path.display());
-                (StatusCode::OK, ()).into_response()
+fn formatter<'a>(args: &'a RunArgs, config: &'a Config) -> output::Formatter<'a> {
+    output::Formatter::new(
+        args.options.verbose.unwrap_or_default(),
+        args.options.silent.unwrap_or_default(),
+        config,
+        &args.path,
+    )
+}
+
+async fn report(
+    args: &RunArgs,
+    config: &Config,
+    output: &Output,
+    streamed: &HashSet<String>,
+) -> anyhow::Result<()> {
+    for reporter in args.reporters() {
+        match reporter {
+            Reporter::Default => {
+                print!(
+                    "{}",
+                    formatter(args, config).format_output(output, streamed)
+                );
             }
-            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-        },
-        None => (StatusCode::NOT_FOUND, "Could not resolve path").into_response(),
+            Reporter::Junit => write_report(args, &output::junit::format(output)).await?,
+            Reporter::Json => write_report(args, &output::json::format(output)).await?,
+            Reporter::Github => print!("{}", output::github::format(output, config, &args.path)),
+        }
     }
+
+    Ok(())
+}
+
+async fn write_report(args: &RunArgs, report: &str) -> anyhow::Result<()> {
+    match &args.output_file {
+        Some(path) => {
+            fs::write(path, report).await?;
+            info!("Report written to {}", path.display());
+        }
+        None => print!("{report}"),
+    }
+
+    Ok(())
 }
@@ -1,28 +1,84 @@
+use crate::{
+    cache::Failures,
+    config::Config,
+    resolver::{resolve_dotted_script, resolve_script},
+    stack_trace,
+};
 use colored::*;
-use serde::Deserialize;
-use std::fmt::{self, Write as _};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt::{self, Write as _},
+    path::{Path, PathBuf},
+};
 
-pub struct Formatter {
+/// How many lines to show either side of the failing line in code frames.
+const CODE_FRAME_CONTEXT: usize = 2;
+
+pub mod github;
+pub mod json;
+pub mod junit;
+
+pub struct Formatter<'a> {
     verbose: bool,
+    silent: bool,
+    config: &'a Config,
+    project_root: &'a Path,
 }
 
-impl Formatter {
-    pub fn new(verbose: bool) -> Self {
-        Self { verbose }
+impl<'a> Formatter<'a> {
+    pub fn new(verbose: bool, silent: bool, config: &'a Config, project_root: &'a Path) -> Self {
+        Self {
+            verbose,
+            silent,
+            config,
+            project_root,
+        }
     }
 
-    pub fn format_output(&self, output: &Output) -> String {
+    /// Formats the results of a run. Test files in `streamed` were already printed as they
+    /// finished, so only the summary is printed for them.
+    pub fn format_output(&self, output: &Output, streamed: &HashSet<String>) -> String {
         let mut buf = String::new();
 
-        self.write_test_results(&mut buf, &output.results.test_results)
-            .unwrap();
+        for test_file in &output.results.test_results {
+            if !streamed.contains(&test_file.test_file_path) {
+                self.write_test_file(&mut buf, test_file).unwrap();
+            }
+        }
         writeln!(buf).ok();
 
+        self.write_flaky(&mut buf, &output.results).unwrap();
         self.write_summary(&mut buf, &output.results).unwrap();
 
         buf
     }
 
+    /// Lists the tests that only passed on a retry, since their files were usually printed before
+    /// we knew.
+    fn write_flaky(&self, buf: &mut String, results: &Results) -> fmt::Result {
+        if results.num_flaky_tests == 0 {
+            return Ok(());
+        }
+
+        writeln!(buf, "{}", "Flaky tests:".yellow().bold())?;
+
+        for test_file in &results.test_results {
+            for case in test_file.test_results.iter().filter(|c| c.flaky) {
+                writeln!(
+                    buf,
+                    "  {} {} {} {}",
+                    "●".yellow(),
+                    self.display_path(&test_file.test_file_path),
+                    "›".dimmed(),
+                    case.full_name()
+                )?;
+            }
+        }
+
+        writeln!(buf)
+    }
+
     fn write_summary(&self, buf: &mut String, results: &Results) -> fmt::Result {
         // Suites
         writeln!(buf, "{}", "Test Suites:".bold())?;
@@ -71,6 +127,12 @@ impl Formatter {
                 results.num_failed_tests.to_string().red().bold()
             ));
         }
+        if results.num_flaky_tests > 0 {
+            test_parts.push(format!(
+                "{} flaky",
+                results.num_flaky_tests.to_string().yellow()
+            ));
+        }
         if results.num_pending_tests > 0 {
             test_parts.push(format!(
                 "{} pending",
@@ -101,14 +163,54 @@ impl Formatter {
         let total_duration = self.total_duration(&results.test_results);
         writeln!(buf, "{} {}ms", "Time:".bold(), total_duration)?;
 
+        if results.was_interrupted {
+            writeln!(buf, "{}", "Test run was interrupted.".red().bold())?;
+        }
+
         Ok(())
     }
 
-    fn write_test_results(&self, buf: &mut String, test_results: &[TestResult]) -> fmt::Result {
-        for test in test_results {
-            self.write_test_file(buf, test)?;
+    pub fn format_test_file(&self, test_file: &TestResult) -> String {
+        let mut buf = String::new();
+        self.write_test_file(&mut buf, test_file).unwrap();
+        buf
+    }
+
+    pub fn format_logs(&self, logs: &Logs) -> String {
+        let mut buf = String::new();
+        self.write_logs(&mut buf, logs).unwrap();
+        buf
+    }
+
+    fn write_logs(&self, buf: &mut String, logs: &Logs) -> fmt::Result {
+        match &logs.test_file_path {
+            Some(path) => writeln!(
+                buf,
+                "{} {}",
+                "Output from".dimmed(),
+                self.display_path(path)
+            )?,
+            None => writeln!(buf, "{}", "Output".dimmed())?,
         }
-        Ok(())
+
+        for log in &logs.messages {
+            let color = match log.message_type {
+                MessageType::Output => None,
+                MessageType::Info => Some(Color::Blue),
+                MessageType::Warning => Some(Color::Yellow),
+                MessageType::Error => Some(Color::Red),
+            };
+            self.write_indented(buf, &log.message, 2, color)?;
+        }
+
+        writeln!(buf)
+    }
+
+    /// Shows the file on disk if we can find it, so it can be opened from the terminal.
+    fn display_path(&self, instance_path: &str) -> String {
+        resolve_script(self.config, instance_path, self.project_root)
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| instance_path.to_string())
     }
 
     fn write_test_file(&self, buf: &mut String, test_file: &TestResult) -> fmt::Result {
@@ -119,27 +221,39 @@ impl Formatter {
             ("✓", "green")
         };
 
+        let path = self.display_path(&test_file.test_file_path);
+
         writeln!(
             buf,
             "{} {} {}",
             icon.color(color).bold(),
-            test_file.test_file_path.bold(),
+            path.bold(),
             format!("({}ms)", test_file.perf_stats.runtime).dimmed()
         )?;
 
+        if !self.silent {
+            self.write_console(buf, test_file)?;
+        }
+
         if let Some(msg) = &test_file.failure_message
             && test_file.test_results.is_empty()
         {
             writeln!(buf, "  {} Test suite failed to run", "●".red().bold())?;
             writeln!(buf)?;
-            self.write_indented(buf, msg, 2, Some(Color::BrightRed))?;
+            self.write_indented(
+                buf,
+                &stack_trace::rewrite(msg, self.config, self.project_root),
+                2,
+                Some(Color::BrightRed),
+            )?;
+            self.write_code_frame(buf, test_file, std::slice::from_ref(msg), 2)?;
             return Ok(());
         }
 
         let mut current_ancestors: Vec<String> = Vec::new();
 
         for case in &test_file.test_results {
-            let show_test = matches!(case.status, Status::Failed) || self.verbose;
+            let show_test = matches!(case.status, Status::Failed) || case.flaky || self.verbose;
             if !show_test {
                 continue;
             }
@@ -184,10 +298,17 @@ impl Formatter {
                         duration.dimmed()
                     )?;
 
+                    let error_indent = "    ".repeat(case.ancestor_titles.len() + 2);
                     for msg in &case.failure_messages {
-                        let error_indent = "    ".repeat(case.ancestor_titles.len() + 2);
-                        self.write_indented(buf, msg, error_indent.len(), Some(Color::BrightRed))?;
+                        let msg = stack_trace::rewrite(msg, self.config, self.project_root);
+                        self.write_indented(buf, &msg, error_indent.len(), Some(Color::BrightRed))?;
                     }
+                    self.write_code_frame(
+                        buf,
+                        test_file,
+                        &case.failure_messages,
+                        error_indent.len(),
+                    )?;
                 }
                 Status::Passed => {
                     let duration = case
@@ -195,12 +316,19 @@ impl Formatter {
                         .map(|d| format!(" ({}ms)", d))
                         .unwrap_or_default();
 
+                    let flaky = if case.flaky {
+                        " (flaky)".yellow().to_string()
+                    } else {
+                        String::new()
+                    };
+
                     writeln!(
                         buf,
-                        "{}{} {}{}",
+                        "{}{} {}{}{}",
                         test_indent,
                         "✓".green(),
                         case.title,
+                        flaky,
                         duration.dimmed()
                     )?;
                 }
@@ -223,6 +351,39 @@ impl Formatter {
         Ok(())
     }
 
+    /// Prints what the test file logged through jest's console, like Jest does above its results.
+    fn write_console(&self, buf: &mut String, test_file: &TestResult) -> fmt::Result {
+        let Some(console) = &test_file.console else {
+            return Ok(());
+        };
+
+        for entry in console {
+            let color = match entry.r#type.as_str() {
+                "error" => Some(Color::Red),
+                "warn" => Some(Color::Yellow),
+                "info" => Some(Color::Blue),
+                _ => None,
+            };
+            let header = format!("console.{}", entry.r#type);
+
+            match color {
+                Some(c) => writeln!(buf, "  {}", header.color(c).bold())?,
+                None => writeln!(buf, "  {}", header.bold())?,
+            }
+            self.write_indented(buf, &entry.message, 4, color)?;
+
+            // The origin is a stack trace, but only where it was logged from is interesting
+            if let Some(origin) = entry.origin.lines().find(|l| !l.trim().is_empty()) {
+                let origin = stack_trace::rewrite(origin.trim(), self.config, self.project_root);
+                writeln!(buf)?;
+                writeln!(buf, "      {} {}", "at".dimmed(), origin.dimmed())?;
+            }
+            writeln!(buf)?;
+        }
+
+        Ok(())
+    }
+
     fn write_indented(
         &self,
         buf: &mut String,
@@ -245,12 +406,141 @@ impl Formatter {
         Ok(())
     }
 
+    /// Prints the source around where a test failed, like Jest's code frames.
+    fn write_code_frame(
+        &self,
+        buf: &mut String,
+        test_file: &TestResult,
+        messages: &[String],
+        indent: usize,
+    ) -> fmt::Result {
+        let Some((path, line)) = self.failure_location(test_file, messages) else {
+            return Ok(());
+        };
+        let Ok(source) = fs_err::read_to_string(&path) else {
+            return Ok(());
+        };
+
+        let lines: Vec<&str> = source.lines().collect();
+        if line == 0 || line > lines.len() {
+            return Ok(());
+        }
+
+        let first = line.saturating_sub(CODE_FRAME_CONTEXT + 1);
+        let last = (line + CODE_FRAME_CONTEXT).min(lines.len());
+        let width = last.to_string().len();
+        let prefix = " ".repeat(indent);
+
+        writeln!(buf)?;
+
+        for (i, text) in lines.iter().enumerate().take(last).skip(first) {
+            let n = i + 1;
+            let gutter = format!("{n:>width$} |");
+
+            if n == line {
+                writeln!(buf, "{prefix}{} {} {text}", ">".red().bold(), gutter)?;
+
+                let whitespace: String = text.chars().take_while(|c| c.is_whitespace()).collect();
+                writeln!(
+                    buf,
+                    "{prefix}  {} {whitespace}{}",
+                    format!("{:width$} |", "").dimmed(),
+                    "^".red().bold()
+                )?;
+            } else {
+                writeln!(buf, "{prefix}  {} {text}", gutter.dimmed())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds the file and line a test failed at. Frames in the test file itself are preferred, then
+    /// the first one we can find on disk that isn't in a package.
+    fn failure_location(
+        &self,
+        test_file: &TestResult,
+        messages: &[String],
+    ) -> Option<(PathBuf, usize)> {
+        let test_file_path = test_file.test_file_path.replace('/', ".");
+
+        let frames: Vec<_> = messages
+            .iter()
+            .flat_map(|msg| stack_trace::parse(msg))
+            .collect();
+
+        let in_test_file = frames
+            .iter()
+            .filter(|frame| frame.instance_path == test_file_path);
+        let elsewhere = frames.iter().filter(|frame| {
+            !frame
+                .instance_path
+                .split('.')
+                .any(|name| name == "_Index" || name == "node_modules")
+        });
+
+        in_test_file.chain(elsewhere).find_map(|frame| {
+            let path = resolve_dotted_script(self.config, frame.instance_path, self.project_root)?;
+            Some((path, frame.line as usize))
+        })
+    }
+
     fn total_duration(&self, results: &[TestResult]) -> u64 {
         results.iter().map(|r| r.perf_stats.runtime).sum()
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// Removes ANSI color codes, for reporters whose output isn't shown in a terminal.
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.clone().next() == Some('[') {
+            // Skip the rest of the escape sequence, e.g. `[31m`
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+/// Messages printed to the Studio output while a test file was running.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Logs {
+    #[serde(default)]
+    test_file_path: Option<String>,
+    messages: Vec<LogMessage>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LogMessage {
+    message: String,
+    message_type: MessageType,
+}
+
+/// Names match Roblox's `Enum.MessageType`.
+#[derive(Debug, Deserialize, Serialize)]
+enum MessageType {
+    #[serde(rename = "MessageOutput")]
+    Output,
+    #[serde(rename = "MessageInfo")]
+    Info,
+    #[serde(rename = "MessageWarning")]
+    Warning,
+    #[serde(rename = "MessageError")]
+    Error,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Output {
     results: Results,
@@ -260,12 +550,258 @@ impl Output {
     pub fn was_successful(&self) -> bool {
         self.results.success
     }
+
+    /// Combines the results of runs over separate test files, like the shards of a run.
+    pub fn merge(outputs: Vec<Output>) -> Output {
+        let mut outputs = outputs.into_iter();
+        let mut merged = outputs.next().expect("nothing to merge");
+
+        for output in outputs {
+            merged.results.merge(output.results);
+        }
+
+        merged
+    }
+
+    /// Builds the results of a run that was stopped early from the test files that finished.
+    pub fn interrupted(test_results: Vec<TestResult>, num_total_test_suites: u32) -> Output {
+        let start_time = test_results
+            .iter()
+            .map(|r| r.perf_stats.start)
+            .reduce(f64::min)
+            .unwrap_or_default();
+
+        let mut results = Results {
+            success: false,
+            start_time,
+            was_interrupted: true,
+            num_failed_test_suites: 0,
+            num_failed_tests: 0,
+            num_passed_test_suites: 0,
+            num_passed_tests: 0,
+            num_pending_tests: 0,
+            num_todo_tests: 0,
+            num_pending_test_suites: 0,
+            num_runtime_error_test_suites: 0,
+            num_total_test_suites,
+            num_total_tests: 0,
+            num_flaky_tests: 0,
+            snapshot: SnapshotSummary::default(),
+            test_results,
+        };
+        results.recount();
+
+        Output { results }
+    }
+
+    /// Merges in the results of running this run's failed tests again. Tests that passed this time
+    /// are marked as flaky.
+    pub fn apply_retry(&mut self, retry: Output) {
+        for retried in retry.results.test_results {
+            let Some(original) = self
+                .results
+                .test_results
+                .iter_mut()
+                .find(|r| r.test_file_path == retried.test_file_path)
+            else {
+                continue;
+            };
+
+            // There weren't any test results to retry, so the new ones replace it. Anything that
+            // passes now didn't get the chance to before.
+            if original.failed_to_run() {
+                *original = retried;
+                for case in &mut original.test_results {
+                    if matches!(case.status, Status::Passed) {
+                        case.flaky = true;
+                    }
+                }
+                continue;
+            }
+
+            for case in &retried.test_results {
+                if !matches!(case.status, Status::Passed) {
+                    continue;
+                }
+
+                let full_name = case.full_name();
+                if let Some(failed) = original
+                    .test_results
+                    .iter_mut()
+                    .find(|c| matches!(c.status, Status::Failed) && c.full_name() == full_name)
+                {
+                    failed.status = Status::Passed;
+                    failed.flaky = true;
+                }
+            }
+
+            original.recount();
+        }
+
+        let results = &mut self.results;
+        results.recount();
+        results.success = results.num_failed_test_suites == 0
+            && results.num_runtime_error_test_suites == 0
+            && !results.was_interrupted
+            && !results.snapshot.failure;
+    }
+
+    pub fn was_interrupted(&self) -> bool {
+        self.results.was_interrupted
+    }
+
+    /// Updates what failed last time with how the tests in this run went.
+    pub fn update_failures(&self, failures: &mut Failures) {
+        for test_file in &self.results.test_results {
+            if test_file.failed_to_run() {
+                failures.insert(test_file.test_file_path.clone(), Vec::new());
+                continue;
+            }
+
+            let names = failures
+                .entry(test_file.test_file_path.clone())
+                .or_default();
+
+            for case in &test_file.test_results {
+                let full_name = case.full_name();
+
+                match case.status {
+                    Status::Failed if !names.contains(&full_name) => names.push(full_name),
+                    Status::Passed => names.retain(|n| *n != full_name),
+                    _ => {}
+                }
+            }
+
+            if names.is_empty() {
+                failures.remove(&test_file.test_file_path);
+            }
+        }
+    }
+
+    /// How long each test file took to run, in milliseconds.
+    pub fn file_runtimes(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.results
+            .test_results
+            .iter()
+            .map(|r| (r.test_file_path.as_str(), r.perf_stats.runtime))
+    }
 }
 
-#[derive(Debug, Deserialize)]
+impl Results {
+    /// Counts the test files and tests again, after they changed.
+    fn recount(&mut self) {
+        self.num_failed_test_suites = 0;
+        self.num_passed_test_suites = 0;
+        self.num_pending_test_suites = 0;
+        self.num_runtime_error_test_suites = 0;
+        self.num_failed_tests = 0;
+        self.num_passed_tests = 0;
+        self.num_pending_tests = 0;
+        self.num_todo_tests = 0;
+        self.num_flaky_tests = 0;
+
+        for r in &self.test_results {
+            if r.failed_to_run() {
+                self.num_runtime_error_test_suites += 1;
+            } else if r.num_failing_tests > 0 {
+                self.num_failed_test_suites += 1;
+            } else if r.skipped {
+                self.num_pending_test_suites += 1;
+            } else {
+                self.num_passed_test_suites += 1;
+            }
+
+            self.num_failed_tests += r.num_failing_tests;
+            self.num_passed_tests += r.num_passing_tests;
+            self.num_pending_tests += r.num_pending_tests;
+            self.num_todo_tests += r.num_todo_tests;
+            self.num_flaky_tests += r.test_results.iter().filter(|c| c.flaky).count() as u32;
+        }
+
+        self.num_total_tests = self.num_failed_tests
+            + self.num_passed_tests
+            + self.num_pending_tests
+            + self.num_todo_tests;
+    }
+
+    fn merge(&mut self, other: Results) {
+        self.success &= other.success;
+        self.start_time = self.start_time.min(other.start_time);
+        self.was_interrupted |= other.was_interrupted;
+        self.num_failed_test_suites += other.num_failed_test_suites;
+        self.num_failed_tests += other.num_failed_tests;
+        self.num_passed_test_suites += other.num_passed_test_suites;
+        self.num_passed_tests += other.num_passed_tests;
+        self.num_pending_tests += other.num_pending_tests;
+        self.num_todo_tests += other.num_todo_tests;
+        self.num_pending_test_suites += other.num_pending_test_suites;
+        self.num_runtime_error_test_suites += other.num_runtime_error_test_suites;
+        self.num_total_test_suites += other.num_total_test_suites;
+        self.num_total_tests += other.num_total_tests;
+        self.num_flaky_tests += other.num_flaky_tests;
+        self.snapshot.merge(other.snapshot);
+        self.test_results.extend(other.test_results);
+    }
+}
+
+impl SnapshotSummary {
+    fn merge(&mut self, other: SnapshotSummary) {
+        self.added += other.added;
+        self.did_update |= other.did_update;
+        self.failure |= other.failure;
+        self.files_added += other.files_added;
+        self.files_removed += other.files_removed;
+        self.files_unmatched += other.files_unmatched;
+        self.files_updated += other.files_updated;
+        self.matched += other.matched;
+        self.total += other.total;
+        self.unchecked += other.unchecked;
+        self.unmatched += other.unmatched;
+        self.updated += other.updated;
+    }
+}
+
+impl TestResult {
+    pub fn test_file_path(&self) -> &str {
+        &self.test_file_path
+    }
+
+    /// Whether this test file had failing tests or failed to run.
+    pub fn failed(&self) -> bool {
+        self.num_failing_tests > 0 || self.failure_message.is_some()
+    }
+
+    fn failed_to_run(&self) -> bool {
+        self.failure_message.is_some() && self.test_results.is_empty()
+    }
+
+    /// Counts the tests again, after some of them changed.
+    fn recount(&mut self) {
+        let count = |status: fn(&Status) -> bool| {
+            self.test_results
+                .iter()
+                .filter(|c| status(&c.status))
+                .count() as u32
+        };
+
+        self.num_failing_tests = count(|s| matches!(s, Status::Failed));
+        self.num_passing_tests = count(|s| matches!(s, Status::Passed));
+
+        // The message is only about the tests that failed
+        if self.num_failing_tests == 0 {
+            self.failure_message = None;
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct Results {
     pub success: bool,
+    #[serde(default)]
+    start_time: f64,
+    #[serde(default)]
+    was_interrupted: bool,
     num_failed_test_suites: u32,
     num_failed_tests: u32,
     num_passed_test_suites: u32,
@@ -276,30 +812,106 @@ struct Results {
     num_runtime_error_test_suites: u32,
     num_total_test_suites: u32,
     num_total_tests: u32,
+    /// Tests that failed, but passed when retried.
+    #[serde(default)]
+    num_flaky_tests: u32,
+    #[serde(default)]
+    snapshot: SnapshotSummary,
     test_results: Vec<TestResult>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+struct SnapshotSummary {
+    added: u32,
+    did_update: bool,
+    failure: bool,
+    files_added: u32,
+    files_removed: u32,
+    files_unmatched: u32,
+    files_updated: u32,
+    matched: u32,
+    total: u32,
+    unchecked: u32,
+    unmatched: u32,
+    updated: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-struct TestResult {
+pub struct TestResult {
     test_file_path: String,
+    #[serde(default)]
+    display_name: Option<String>,
     num_failing_tests: u32,
+    #[serde(default)]
+    num_passing_tests: u32,
+    #[serde(default)]
+    num_pending_tests: u32,
+    #[serde(default)]
+    num_todo_tests: u32,
+    #[serde(default)]
+    skipped: bool,
     test_results: Vec<AssertionResult>,
     perf_stats: PerfStats,
     failure_message: Option<String>,
+    #[serde(default)]
+    snapshot: FileSnapshotSummary,
+    /// What the test file logged through jest's console. Missing when nothing was logged.
+    #[serde(default)]
+    console: Option<Vec<ConsoleEntry>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ConsoleEntry {
+    message: String,
+    #[serde(default)]
+    origin: String,
+    r#type: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+struct FileSnapshotSummary {
+    added: u32,
+    file_deleted: bool,
+    matched: u32,
+    unchecked: u32,
+    unmatched: u32,
+    updated: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct AssertionResult {
     title: String,
+    #[serde(default)]
+    full_name: String,
     status: Status,
     failure_messages: Vec<String>,
     duration: Option<u64>,
     ancestor_titles: Vec<String>,
+    #[serde(default)]
+    num_passing_asserts: u32,
+    /// Failed, but passed when retried.
+    #[serde(default)]
+    flaky: bool,
+}
+
+impl AssertionResult {
+    /// The name jest-lua matches `testNamePattern` against.
+    fn full_name(&self) -> String {
+        if !self.full_name.is_empty() {
+            return self.full_name.clone();
+        }
+
+        let mut parts = self.ancestor_titles.clone();
+        parts.push(self.title.clone());
+        parts.join(" ")
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 enum Status {
     Passed,
@@ -310,8 +922,174 @@ enum Status {
     Disabled,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct PerfStats {
+    #[serde(default)]
+    start: f64,
+    #[serde(default)]
+    end: f64,
     runtime: u64,
+    #[serde(default)]
+    slow: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn case(name: &str, status: &str) -> Value {
+        json!({
+            "title": name,
+            "status": status,
+            "failureMessages": [],
+            "duration": 1,
+            "ancestorTitles": ["Foo"],
+        })
+    }
+
+    fn test_file(path: &str, cases: Vec<Value>) -> Value {
+        let count = |status: &str| cases.iter().filter(|c| c["status"] == status).count();
+        let failing = count("failed");
+
+        json!({
+            "testFilePath": path,
+            "numFailingTests": failing,
+            "numPassingTests": count("passed"),
+            "testResults": cases,
+            "perfStats": { "runtime": 1 },
+            "failureMessage": (failing > 0).then_some("failed"),
+        })
+    }
+
+    /// A file that errored before any of its tests ran.
+    fn failed_to_run(path: &str) -> Value {
+        json!({
+            "testFilePath": path,
+            "numFailingTests": 0,
+            "testResults": [],
+            "perfStats": { "runtime": 1 },
+            "failureMessage": "Requested module was required recursively",
+        })
+    }
+
+    fn output(test_files: Vec<Value>) -> Output {
+        let mut results: Results = serde_json::from_value(json!({
+            "success": false,
+            "numFailedTestSuites": 0,
+            "numFailedTests": 0,
+            "numPassedTestSuites": 0,
+            "numPassedTests": 0,
+            "numPendingTests": 0,
+            "numTodoTests": 0,
+            "numPendingTestSuites": 0,
+            "numRuntimeErrorTestSuites": 0,
+            "numTotalTestSuites": test_files.len(),
+            "numTotalTests": 0,
+            "testResults": test_files,
+        }))
+        .unwrap();
+        results.recount();
+
+        Output { results }
+    }
+
+    #[test]
+    fn records_failures() {
+        let output = output(vec![
+            test_file("A.spec", vec![case("a", "failed"), case("b", "passed")]),
+            test_file("B.spec", vec![case("c", "passed")]),
+            failed_to_run("C.spec"),
+        ]);
+
+        let mut failures = Failures::new();
+        output.update_failures(&mut failures);
+
+        assert_eq!(
+            failures,
+            Failures::from([
+                ("A.spec".into(), vec!["Foo a".into()]),
+                ("C.spec".into(), Vec::new()),
+            ])
+        );
+    }
+
+    #[test]
+    fn forgets_failures_that_pass() {
+        let mut failures = Failures::from([
+            ("A.spec".into(), vec!["Foo a".into(), "Foo b".into()]),
+            ("B.spec".into(), vec!["Foo c".into()]),
+            ("Other.spec".into(), vec!["Foo d".into()]),
+        ]);
+
+        output(vec![
+            test_file("A.spec", vec![case("a", "passed")]),
+            test_file("B.spec", vec![case("c", "passed")]),
+        ])
+        .update_failures(&mut failures);
+
+        // Tests that didn't run this time are still failing, as far as we know
+        assert_eq!(
+            failures,
+            Failures::from([
+                ("A.spec".into(), vec!["Foo b".into()]),
+                ("Other.spec".into(), vec!["Foo d".into()]),
+            ])
+        );
+    }
+
+    #[test]
+    fn marks_tests_that_pass_on_retry_as_flaky() {
+        let mut original = output(vec![
+            test_file("A.spec", vec![case("a", "failed"), case("b", "failed")]),
+            test_file("B.spec", vec![case("c", "passed")]),
+        ]);
+
+        original.apply_retry(output(vec![test_file(
+            "A.spec",
+            vec![case("a", "passed"), case("b", "failed")],
+        )]));
+
+        let results = &original.results;
+        let cases = &results.test_results[0].test_results;
+        assert!(cases[0].flaky && matches!(cases[0].status, Status::Passed));
+        assert!(!cases[1].flaky && matches!(cases[1].status, Status::Failed));
+        assert_eq!(results.num_flaky_tests, 1);
+        assert_eq!(results.num_failed_tests, 1);
+        assert_eq!(results.num_passed_tests, 2);
+        assert!(!results.success);
+    }
+
+    #[test]
+    fn succeeds_when_every_failure_passes_on_retry() {
+        let mut original = output(vec![test_file("A.spec", vec![case("a", "failed")])]);
+
+        original.apply_retry(output(vec![test_file("A.spec", vec![case("a", "passed")])]));
+
+        let results = &original.results;
+        assert!(results.success);
+        assert_eq!(results.num_failed_test_suites, 0);
+        assert_eq!(results.num_passed_test_suites, 1);
+        assert_eq!(results.test_results[0].failure_message, None);
+    }
+
+    #[test]
+    fn replaces_files_that_failed_to_run() {
+        let mut original = output(vec![failed_to_run("A.spec")]);
+        assert_eq!(original.results.num_runtime_error_test_suites, 1);
+
+        original.apply_retry(output(vec![test_file(
+            "A.spec",
+            vec![case("a", "passed"), case("b", "failed")],
+        )]));
+
+        let results = &original.results;
+        let cases = &results.test_results[0].test_results;
+        assert!(cases[0].flaky);
+        assert!(!cases[1].flaky);
+        assert_eq!(results.num_runtime_error_test_suites, 0);
+        assert_eq!(results.num_failed_test_suites, 1);
+        assert_eq!(results.num_flaky_tests, 1);
+    }
 }
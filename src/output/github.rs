@@ -0,0 +1,91 @@
+use super::{Output, Status, TestResult, strip_ansi};
+use crate::{config::Config, resolver::resolve_script, stack_trace};
+use std::{
+    fmt::{self, Write as _},
+    path::Path,
+};
+
+/// Formats failures as GitHub Actions workflow commands, which show up as annotations on the
+/// pull request diff.
+pub fn format(output: &Output, config: &Config, project_root: &Path) -> String {
+    let mut buf = String::new();
+
+    for test_file in &output.results.test_results {
+        write_test_file(&mut buf, test_file, config, project_root).unwrap();
+    }
+
+    buf
+}
+
+fn write_test_file(
+    buf: &mut String,
+    test_file: &TestResult,
+    config: &Config,
+    project_root: &Path,
+) -> fmt::Result {
+    let file = resolve_script(config, &test_file.test_file_path, project_root);
+
+    let mut properties = Vec::new();
+    if let Some(file) = &file {
+        properties.push(format!("file={}", escape_property(&file.to_string_lossy())));
+    }
+
+    if let Some(msg) = &test_file.failure_message
+        && test_file.failed_to_run()
+    {
+        properties.push("title=Test suite failed to run".to_string());
+        return writeln!(
+            buf,
+            "::error {}::{}",
+            properties.join(","),
+            escape_data(msg)
+        );
+    }
+
+    for case in &test_file.test_results {
+        if !matches!(case.status, Status::Failed) {
+            continue;
+        }
+
+        let message = case.failure_messages.join("\n");
+
+        let mut properties = properties.clone();
+        if file.is_some()
+            && let Some(line) = find_line(&message, &test_file.test_file_path)
+        {
+            properties.push(format!("line={line}"));
+        }
+
+        properties.push(format!("title={}", escape_property(&case.full_name())));
+
+        writeln!(
+            buf,
+            "::error {}::{}",
+            properties.join(","),
+            escape_data(&message)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Looks for a stack frame in the test file itself, like `ReplicatedStorage.Shared.Foo.spec:42`.
+fn find_line(message: &str, test_file_path: &str) -> Option<u32> {
+    let dotted = test_file_path.replace('/', ".");
+
+    stack_trace::parse(message)
+        .into_iter()
+        .find(|frame| frame.instance_path == dotted)
+        .map(|frame| frame.line)
+}
+
+fn escape_data(text: &str) -> String {
+    strip_ansi(text)
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
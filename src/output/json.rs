@@ -0,0 +1,10 @@
+use super::Output;
+
+/// Serializes the results as jest-lua's `AggregatedResult`, which isn't the same shape as jest's
+/// `--json` output. The schema is documented in the README, so fields shouldn't be renamed or
+/// removed.
+pub fn format(output: &Output) -> String {
+    let mut json = serde_json::to_string_pretty(&output.results).unwrap();
+    json.push('\n');
+    json
+}
@@ -0,0 +1,146 @@
+use super::{AssertionResult, Output, Status, TestResult, strip_ansi};
+use std::fmt::{self, Write as _};
+
+pub fn format(output: &Output) -> String {
+    let mut buf = String::new();
+    write_test_suites(&mut buf, output).unwrap();
+    buf
+}
+
+fn write_test_suites(buf: &mut String, output: &Output) -> fmt::Result {
+    let results = &output.results;
+
+    let total_time: u64 = results
+        .test_results
+        .iter()
+        .map(|r| r.perf_stats.runtime)
+        .sum();
+    let errors = results
+        .test_results
+        .iter()
+        .filter(|r| r.failed_to_run())
+        .count();
+
+    writeln!(buf, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        buf,
+        r#"<testsuites name="jest tests" tests="{}" failures="{}" errors="{}" time="{}">"#,
+        results.num_total_tests,
+        results.num_failed_tests,
+        errors,
+        seconds(total_time)
+    )?;
+
+    for test_file in &results.test_results {
+        write_test_suite(buf, test_file)?;
+    }
+
+    writeln!(buf, "</testsuites>")
+}
+
+fn write_test_suite(buf: &mut String, test_file: &TestResult) -> fmt::Result {
+    let path = escape(&test_file.test_file_path);
+
+    if test_file.failed_to_run() {
+        let message = test_file.failure_message.as_deref().unwrap_or_default();
+
+        writeln!(
+            buf,
+            r#"  <testsuite name="{path}" tests="1" failures="0" errors="1" skipped="0" time="{}">"#,
+            seconds(test_file.perf_stats.runtime)
+        )?;
+        writeln!(
+            buf,
+            r#"    <testcase classname="{path}" name="Test suite failed to run" time="0">"#
+        )?;
+        writeln!(buf, "      <error>{}</error>", escape(message))?;
+        writeln!(buf, "    </testcase>")?;
+        return writeln!(buf, "  </testsuite>");
+    }
+
+    let cases = &test_file.test_results;
+    let failures = cases
+        .iter()
+        .filter(|c| matches!(c.status, Status::Failed))
+        .count();
+    let skipped = cases.iter().filter(|c| is_skipped(c)).count();
+
+    writeln!(
+        buf,
+        r#"  <testsuite name="{path}" tests="{}" failures="{failures}" errors="0" skipped="{skipped}" time="{}">"#,
+        cases.len(),
+        seconds(test_file.perf_stats.runtime)
+    )?;
+
+    for case in cases {
+        write_test_case(buf, test_file, case)?;
+    }
+
+    writeln!(buf, "  </testsuite>")
+}
+
+fn write_test_case(
+    buf: &mut String,
+    test_file: &TestResult,
+    case: &AssertionResult,
+) -> fmt::Result {
+    let classname = if case.ancestor_titles.is_empty() {
+        test_file.test_file_path.clone()
+    } else {
+        case.ancestor_titles.join(" ")
+    };
+
+    write!(
+        buf,
+        r#"    <testcase classname="{}" name="{}" time="{}""#,
+        escape(&classname),
+        escape(&case.title),
+        seconds(case.duration.unwrap_or_default())
+    )?;
+
+    if matches!(case.status, Status::Failed) {
+        writeln!(buf, ">")?;
+        for msg in &case.failure_messages {
+            writeln!(buf, "      <failure>{}</failure>", escape(msg))?;
+        }
+        writeln!(buf, "    </testcase>")
+    } else if is_skipped(case) {
+        writeln!(buf, ">")?;
+        writeln!(buf, "      <skipped/>")?;
+        writeln!(buf, "    </testcase>")
+    } else {
+        writeln!(buf, "/>")
+    }
+}
+
+fn is_skipped(case: &AssertionResult) -> bool {
+    matches!(
+        case.status,
+        Status::Skipped | Status::Pending | Status::Todo | Status::Disabled
+    )
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{:.3}", ms as f64 / 1000.0)
+}
+
+/// Escapes text for use in attributes and element content, dropping ANSI color codes and any other
+/// control characters XML doesn't allow.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for c in strip_ansi(text).chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+
+    out
+}
@@ -0,0 +1,59 @@
+//! Building the regular expressions jest-lua matches test paths and names against.
+
+/// Escapes the characters that are special in JavaScript-style regular expressions, which is what
+/// jest-lua uses.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+
+    out
+}
+
+/// A group matching any of `items` literally.
+pub fn any_of<S: AsRef<str>>(items: impl IntoIterator<Item = S>) -> String {
+    let items: Vec<String> = items.into_iter().map(|i| escape(i.as_ref())).collect();
+    format!("({})", items.join("|"))
+}
+
+/// A `testPathPattern` that only matches these test files, given as instance paths.
+pub fn test_paths<S: AsRef<str>>(paths: impl IntoIterator<Item = S>) -> String {
+    format!("{}$", any_of(paths))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn escapes_special_characters() {
+        assert_eq!(escape("Foo.spec"), r"Foo\.spec");
+        assert_eq!(escape("a (b) [c] {d}"), r"a \(b\) \[c\] \{d\}");
+        assert_eq!(escape(r"^$|?*+\"), r"\^\$\|\?\*\+\\");
+        assert_eq!(escape("plain name"), "plain name");
+    }
+
+    #[test]
+    fn matches_any_item_literally() {
+        let regex = Regex::new(&any_of(["adds 1 + 1", "Foo.spec"])).unwrap();
+
+        assert!(regex.is_match("adds 1 + 1"));
+        assert!(regex.is_match("Foo.spec"));
+        assert!(!regex.is_match("adds 1  1"));
+        assert!(!regex.is_match("Foo_spec"));
+    }
+
+    #[test]
+    fn matches_test_paths_at_the_end() {
+        let regex = Regex::new(&test_paths(["Shared/Foo.spec"])).unwrap();
+
+        assert!(regex.is_match("ReplicatedStorage/Shared/Foo.spec"));
+        assert!(!regex.is_match("ReplicatedStorage/Shared/Foo.spec/Bar"));
+    }
+}
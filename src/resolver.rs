@@ -1,16 +1,24 @@
 use crate::config::Config;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
+/// Suffixes Rojo turns into scripts, in the order we try them.
+const SCRIPT_SUFFIXES: [&str; 6] = [
+    ".luau",
+    ".lua",
+    ".server.luau",
+    ".server.lua",
+    ".client.luau",
+    ".client.lua",
+];
+
+/// Finds where an instance path lives on disk. Paths that would end up outside of their mapping,
+/// through `..` or a symlink, don't resolve.
 pub fn resolve_path(config: &Config, virtual_path: &str, project_root: &Path) -> Option<PathBuf> {
     let p = normalize_virtual(virtual_path);
 
     // Try as-is
     if let Some((base, rem)) = find_longest_prefix(config, &p) {
-        let mut out = project_root.join(base);
-        if !rem.is_empty() {
-            out = out.join(rem);
-        }
-        return Some(out);
+        return join_mapping(&project_root.join(base), rem);
     }
 
     // Try skipping the first segment
@@ -23,12 +31,56 @@ pub fn resolve_path(config: &Config, virtual_path: &str, project_root: &Path) ->
     }
 
     if let Some((base, rem)) = find_longest_prefix(config, &without_first) {
-        let mut out = project_root.join(base);
-        if !rem.is_empty() {
-            out = out.join(rem);
-        }
+        return join_mapping(&project_root.join(base), rem);
+    }
+
+    None
+}
 
-        return Some(out);
+fn join_mapping(base: &Path, rem: &str) -> Option<PathBuf> {
+    let rem = Path::new(rem);
+
+    // Only plain names, so no `..`, roots or drive letters. Colons are checked for separately, since
+    // `C:` is a plain name everywhere but Windows.
+    let plain = |c: Component| match c {
+        Component::Normal(name) => !name.to_string_lossy().contains(':'),
+        _ => false,
+    };
+    if !rem.components().all(plain) {
+        return None;
+    }
+
+    let out = base.join(rem);
+    is_within(base, &out).then_some(out)
+}
+
+/// Whether `path` is inside `dir` once symlinks are followed. Neither has to exist yet.
+pub fn is_within(dir: &Path, path: &Path) -> bool {
+    match (canonicalize_existing(dir), canonicalize_existing(path)) {
+        (Some(dir), Some(path)) => path.starts_with(dir),
+        _ => false,
+    }
+}
+
+/// Canonicalizes the deepest part of `path` that exists, and adds the rest back on. The rest can't
+/// be a symlink, since it doesn't exist.
+fn canonicalize_existing(path: &Path) -> Option<PathBuf> {
+    let path = std::path::absolute(path).ok()?;
+
+    for ancestor in path.ancestors() {
+        if let Ok(real) = ancestor.canonicalize() {
+            let rest = path.strip_prefix(ancestor).ok()?;
+
+            // Anything after that can't be resolved, so `..` in it could go anywhere
+            if rest
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+            {
+                return None;
+            }
+
+            return Some(real.join(rest));
+        }
     }
 
     None
@@ -41,7 +93,7 @@ fn normalize_virtual(p: &str) -> String {
 fn find_longest_prefix<'a>(config: &'a Config, path: &'a str) -> Option<(&'a PathBuf, &'a str)> {
     let mut best: Option<(&PathBuf, &str, usize)> = None;
 
-    for (k, base) in &config.projects {
+    for (k, base) in &config.mappings {
         let k = k.trim_start_matches('/');
 
         if path == k {
@@ -58,3 +110,280 @@ fn find_longest_prefix<'a>(config: &'a Config, path: &'a str) -> Option<(&'a Pat
 
     best.map(|(b, r, _)| (b, r))
 }
+
+/// Finds the file on disk for a script's instance path, like `ReplicatedStorage/Shared/Foo.spec`.
+///
+/// This is the inverse of how Rojo syncs files in: `Foo.spec` can be `Foo.spec.luau` (or any other
+/// script suffix), and a folder-as-module `Foo` is `Foo/init.luau`. The returned path is relative
+/// to the current directory when `project_root` is.
+pub fn resolve_script(
+    config: &Config,
+    instance_path: &str,
+    project_root: &Path,
+) -> Option<PathBuf> {
+    let base = resolve_path(config, instance_path, project_root)?;
+    find_script(&base).map(|path| clean(&path))
+}
+
+/// Like [`resolve_script`], but for the dotted paths Roblox uses in stack traces, like
+/// `ReplicatedStorage.Shared.Foo.spec`. Since instance names can contain dots themselves, we check
+/// the filesystem to work out which dots separate instances.
+pub fn resolve_dotted_script(
+    config: &Config,
+    dotted_path: &str,
+    project_root: &Path,
+) -> Option<PathBuf> {
+    let mut keys: Vec<_> = config.mappings.iter().collect();
+    keys.sort_by_key(|(k, _)| std::cmp::Reverse(k.len()));
+
+    let without_first = dotted_path.split_once('.').map(|(_, rest)| rest);
+
+    for path in std::iter::once(dotted_path).chain(without_first) {
+        for (key, base) in &keys {
+            let key = key.trim_start_matches('/').replace('/', ".");
+            let base = project_root.join(base);
+
+            let found = if path == key {
+                find_script(&base)
+            } else if let Some(rest) = path.strip_prefix(&key).and_then(|s| s.strip_prefix('.')) {
+                let segments: Vec<&str> = rest.split('.').collect();
+                find_dotted_script(&base, &segments)
+            } else {
+                None
+            };
+
+            if let Some(found) = found {
+                return Some(clean(&found));
+            }
+        }
+    }
+
+    None
+}
+
+/// Finds the instance path of a script on disk, like `ReplicatedStorage/Shared/Foo.spec` for
+/// `src/shared/Foo.spec.luau`. This is the inverse of [`resolve_script`].
+pub fn script_instance_path(config: &Config, file: &Path, project_root: &Path) -> Option<String> {
+    let (key, rest) = find_mapping(config, file, project_root)?;
+
+    let name = file.file_name()?.to_str()?;
+    let stem = script_stem(name)?;
+
+    // The mapping might point straight at the file
+    if rest.as_os_str().is_empty() {
+        return Some(key.to_string());
+    }
+
+    let mut segments = path_segments(rest.parent()?)?;
+
+    // Folder modules are named after their folder
+    if stem != "init" {
+        segments.push(stem);
+    }
+
+    Some(join_instance_path(key, segments))
+}
+
+/// Finds the instance path of a directory on disk, like `ReplicatedStorage/Shared` for
+/// `src/shared`.
+pub fn dir_instance_path(config: &Config, dir: &Path, project_root: &Path) -> Option<String> {
+    let (key, rest) = find_mapping(config, dir, project_root)?;
+    Some(join_instance_path(key, path_segments(&rest)?))
+}
+
+/// Finds the mapping a path on disk is under, returning its key and the rest of the path. The
+/// deepest mapping wins when they're nested.
+fn find_mapping<'a>(
+    config: &'a Config,
+    path: &Path,
+    project_root: &Path,
+) -> Option<(&'a str, PathBuf)> {
+    let path = clean(path);
+
+    config
+        .mappings
+        .iter()
+        .filter_map(|(key, base)| {
+            let rest = path.strip_prefix(clean(&project_root.join(base))).ok()?;
+            Some((key.trim_start_matches('/'), rest.to_path_buf()))
+        })
+        .min_by_key(|(_, rest)| rest.components().count())
+}
+
+fn path_segments(path: &Path) -> Option<Vec<&str>> {
+    path.components().map(|c| c.as_os_str().to_str()).collect()
+}
+
+fn join_instance_path(key: &str, segments: Vec<&str>) -> String {
+    let parts: Vec<&str> = std::iter::once(key)
+        .chain(segments)
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    parts.join("/")
+}
+
+/// The name Rojo gives a script file, or `None` if it isn't one.
+fn script_stem(file_name: &str) -> Option<&str> {
+    // Longest first, so `.server.luau` is stripped before `.luau`
+    let mut suffixes = SCRIPT_SUFFIXES;
+    suffixes.sort_by_key(|s| std::cmp::Reverse(s.len()));
+
+    suffixes
+        .iter()
+        .find_map(|suffix| file_name.strip_suffix(suffix))
+}
+
+fn find_dotted_script(dir: &Path, segments: &[&str]) -> Option<PathBuf> {
+    for take in 1..=segments.len() {
+        let path = dir.join(segments[..take].join("."));
+        let rest = &segments[take..];
+
+        let found = if rest.is_empty() {
+            find_script(&path)
+        } else if path.is_dir() {
+            find_dotted_script(&path, rest)
+        } else {
+            None
+        };
+
+        if found.is_some() {
+            return found;
+        }
+    }
+
+    None
+}
+
+fn find_script(base: &Path) -> Option<PathBuf> {
+    // The mapping might point straight at the file
+    if base.is_file() {
+        return Some(base.to_path_buf());
+    }
+
+    let siblings = SCRIPT_SUFFIXES.iter().map(|suffix| {
+        let mut path = base.to_path_buf().into_os_string();
+        path.push(suffix);
+        PathBuf::from(path)
+    });
+    let init_files = SCRIPT_SUFFIXES
+        .iter()
+        .map(|suffix| base.join(format!("init{suffix}")));
+
+    siblings.chain(init_files).find(|path| path.is_file())
+}
+
+/// Drops `.` components, so `./src/foo.luau` becomes `src/foo.luau`.
+fn clean(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// A project with `ReplicatedStorage/Shared` mapped to `src`, and `ReplicatedStorage/Snapshots`
+    /// to `snapshots`, which doesn't exist yet.
+    fn project() -> (TempDir, Config) {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("src")).unwrap();
+
+        let config = toml::from_str(
+            r#"
+            [mappings]
+            "ReplicatedStorage/Shared" = "src"
+            "ReplicatedStorage/Snapshots" = "snapshots"
+            "#,
+        )
+        .unwrap();
+
+        (root, config)
+    }
+
+    #[test]
+    fn resolves_paths_inside_mapping() {
+        let (root, config) = project();
+
+        let path = resolve_path(
+            &config,
+            "ReplicatedStorage/Shared/Foo.snap.luau",
+            root.path(),
+        );
+        assert_eq!(path, Some(root.path().join("src/Foo.snap.luau")));
+    }
+
+    #[test]
+    fn rejects_parent_dirs() {
+        let (root, config) = project();
+
+        for path in [
+            "ReplicatedStorage/Shared/../../pwn.txt",
+            "ReplicatedStorage/Shared/a/../../pwn.txt",
+            "ReplicatedStorage/Shared/..\\..\\pwn.txt",
+            "ReplicatedStorage/Shared/..",
+        ] {
+            assert_eq!(resolve_path(&config, path, root.path()), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn rejects_absolute_and_drive_paths() {
+        let (root, config) = project();
+
+        for path in [
+            "ReplicatedStorage/Shared//etc/passwd",
+            "ReplicatedStorage/Shared/C:/Windows/pwn.txt",
+            "ReplicatedStorage/Shared/C:pwn.txt",
+        ] {
+            assert_eq!(resolve_path(&config, path, root.path()), None, "{path}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_out_of_mapping() {
+        let (root, config) = project();
+        let outside = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("src/escape")).unwrap();
+
+        let path = resolve_path(
+            &config,
+            "ReplicatedStorage/Shared/escape/pwn.txt",
+            root.path(),
+        );
+        assert_eq!(path, None);
+        assert!(!is_within(
+            root.path(),
+            &root.path().join("src/escape/pwn.txt")
+        ));
+    }
+
+    #[test]
+    fn resolves_mappings_that_dont_exist_yet() {
+        let (root, config) = project();
+
+        let path = resolve_path(
+            &config,
+            "ReplicatedStorage/Snapshots/a/Foo.snap.luau",
+            root.path(),
+        );
+        assert_eq!(path, Some(root.path().join("snapshots/a/Foo.snap.luau")));
+        assert!(is_within(root.path(), &path.unwrap()));
+    }
+
+    #[test]
+    fn paths_outside_project_are_not_within_it() {
+        let (root, _) = project();
+        let outside = tempfile::tempdir().unwrap();
+
+        assert!(!is_within(root.path(), &outside.path().join("pwn.txt")));
+        assert!(!is_within(
+            root.path(),
+            &root.path().join("missing/../../pwn.txt")
+        ));
+    }
+}
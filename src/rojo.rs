@@ -0,0 +1,175 @@
+//! Works out where instances live on disk from a Rojo project file, so they don't have to be
+//! listed in `jest-companion.toml` as well.
+
+use anyhow::Context;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_PROJECT: &str = "default.project.json";
+
+/// Reads the mappings out of a project file, from the instance path of every node with a `$path`
+/// to that path. `project` is relative to `project_root`, and so are the paths we return.
+pub fn mappings(project_root: &Path, project: &Path) -> anyhow::Result<HashMap<String, PathBuf>> {
+    let mut mappings = HashMap::new();
+    let tree = read_tree(project_root, project)?;
+
+    // The root is the DataModel itself in a place project, which nothing can be mapped to
+    let base = project.parent().unwrap_or(Path::new(""));
+    if let Some(children) = tree.as_object() {
+        for (name, child) in children.iter().filter(|(name, _)| !name.starts_with('$')) {
+            collect(project_root, base, name, child, &mut mappings)?;
+        }
+    }
+
+    Ok(mappings)
+}
+
+fn read_tree(project_root: &Path, project: &Path) -> anyhow::Result<Value> {
+    let text = fs_err::read_to_string(project_root.join(project))?;
+
+    let mut json: Value = serde_json::from_str(&text)
+        .with_context(|| format!("Failed to parse {}", project.display()))?;
+
+    Ok(json["tree"].take())
+}
+
+fn collect(
+    project_root: &Path,
+    base: &Path,
+    instance_path: &str,
+    node: &Value,
+    mappings: &mut HashMap<String, PathBuf>,
+) -> anyhow::Result<()> {
+    // `$path` is either a string, or `{ "optional": "..." }` for paths that might not exist
+    let path = node["$path"]
+        .as_str()
+        .or_else(|| node["$path"]["optional"].as_str());
+
+    if let Some(path) = path {
+        let path = base.join(path);
+
+        if path.to_string_lossy().ends_with(".project.json") {
+            // Nested projects put their own tree here, with paths relative to them
+            let tree = read_tree(project_root, &path)?;
+            let base = path.parent().unwrap_or(Path::new(""));
+            return collect(project_root, base, instance_path, &tree, mappings);
+        }
+
+        mappings.insert(instance_path.to_string(), path);
+    }
+
+    if let Some(children) = node.as_object() {
+        for (name, child) in children.iter().filter(|(name, _)| !name.starts_with('$')) {
+            let child_path = format!("{instance_path}/{name}");
+            collect(project_root, base, &child_path, child, mappings)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn write(root: &TempDir, path: &str, text: &str) {
+        let path = root.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn reads_mappings() {
+        let root = tempfile::tempdir().unwrap();
+        write(
+            &root,
+            DEFAULT_PROJECT,
+            r#"{
+                "name": "game",
+                "tree": {
+                    "$className": "DataModel",
+                    "ReplicatedStorage": {
+                        "Shared": { "$path": "src/shared" },
+                        "Assets": { "$path": { "optional": "assets" } }
+                    },
+                    "ServerScriptService": {
+                        "$path": "src/server",
+                        "Extra": { "$path": "src/extra" }
+                    }
+                }
+            }"#,
+        );
+
+        let mappings = mappings(root.path(), Path::new(DEFAULT_PROJECT)).unwrap();
+
+        assert_eq!(
+            mappings,
+            HashMap::from([
+                ("ReplicatedStorage/Shared".into(), "src/shared".into()),
+                ("ReplicatedStorage/Assets".into(), "assets".into()),
+                ("ServerScriptService".into(), "src/server".into()),
+                ("ServerScriptService/Extra".into(), "src/extra".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn ignores_the_root_path() {
+        let root = tempfile::tempdir().unwrap();
+        write(
+            &root,
+            DEFAULT_PROJECT,
+            r#"{ "name": "lib", "tree": { "$path": "src" } }"#,
+        );
+
+        let mappings = mappings(root.path(), Path::new(DEFAULT_PROJECT)).unwrap();
+        assert!(mappings.is_empty());
+    }
+
+    #[test]
+    fn follows_nested_projects() {
+        let root = tempfile::tempdir().unwrap();
+        write(
+            &root,
+            "places/game.project.json",
+            r#"{
+                "name": "game",
+                "tree": {
+                    "$className": "DataModel",
+                    "ReplicatedStorage": {
+                        "Lib": { "$path": "../lib/default.project.json" }
+                    }
+                }
+            }"#,
+        );
+        write(
+            &root,
+            "lib/default.project.json",
+            r#"{
+                "name": "lib",
+                "tree": {
+                    "$path": "src",
+                    "Vendor": { "$path": "vendor" }
+                }
+            }"#,
+        );
+
+        let mappings = mappings(root.path(), Path::new("places/game.project.json")).unwrap();
+
+        assert_eq!(
+            mappings,
+            HashMap::from([
+                ("ReplicatedStorage/Lib".into(), "places/../lib/src".into()),
+                (
+                    "ReplicatedStorage/Lib/Vendor".into(),
+                    "places/../lib/vendor".into()
+                ),
+            ])
+        );
+    }
+}
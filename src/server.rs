@@ -0,0 +1,731 @@
+use crate::{
+    auth,
+    cli::JestOptions,
+    config::{Config, DEFAULT_PORT},
+    output::{Logs, Output, TestResult},
+    resolver::{is_within, resolve_path},
+};
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{DefaultBodyLimit, Path as AxumPath, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+};
+use fs_err::tokio as fs;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    convert::Infallible,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, mpsc},
+};
+use tokio_stream::{StreamExt, wrappers::UnboundedReceiverStream};
+
+/// Bumped when the protocol changes in a way older plugins can't work with. Additive features are
+/// negotiated with [`Capability`] instead.
+const PROTOCOL_VERSION: u32 = 6;
+
+/// The oldest plugin we can still run tests with, which is the first one that could be paired.
+const MIN_PROTOCOL_VERSION: u32 = 6;
+
+#[derive(Debug, Clone)]
+pub struct AppState {
+    config: Arc<Config>,
+    project_root: Arc<PathBuf>,
+    /// What the plugin has to send to be let in. See [`auth`].
+    token: Arc<String>,
+    /// Identifies the project to the plugin, which isn't secret.
+    project: Arc<String>,
+    queue: Arc<Mutex<Queue>>,
+}
+
+/// Sent by the plugin with every request, so we can tell Studio instances apart.
+const SESSION_HEADER: &str = "x-jest-companion-session";
+
+/// How many unpaired plugins we remember telling how to pair.
+const MAX_UNPAIRED: usize = 64;
+
+/// How long a plugin can go without polling before we stop counting it as connected.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// Runs waiting to be picked up by the next poll.
+    pending: VecDeque<Job>,
+    /// The runs plugins are working on, by session.
+    active: HashMap<String, Job>,
+    /// Plugins that have polled recently, by session.
+    sessions: HashMap<String, Session>,
+    /// Plugins we've told how to pair, by session.
+    unpaired: HashSet<String>,
+}
+
+#[derive(Debug)]
+struct Session {
+    place: Place,
+    last_seen: Instant,
+}
+
+/// The place open in a Studio instance.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Place {
+    place_id: u64,
+    game_id: u64,
+    place_name: String,
+}
+
+impl Place {
+    fn matches(&self, target: &str) -> bool {
+        target == self.place_name
+            || target == self.place_id.to_string()
+            || target == self.game_id.to_string()
+    }
+
+    fn name(&self) -> &str {
+        if self.place_name.is_empty() {
+            "Studio"
+        } else {
+            &self.place_name
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    options: JestOptions,
+    /// The place to run in, matched against the place name, place ID or game ID.
+    place: Option<String>,
+    sharded: bool,
+    events: mpsc::UnboundedSender<Event>,
+    /// What the plugin running this job agreed to use.
+    capabilities: Vec<Capability>,
+    /// The test files that have finished so far, for when we stop the run early.
+    suites: Vec<TestResult>,
+    num_total_test_suites: u32,
+}
+
+/// Optional features of the protocol, which are only used when both sides support them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Capability {
+    /// Test file results are sent to `/suite-result` as they finish.
+    SuiteResults,
+    /// Studio output is sent to `/logs`.
+    Logs,
+    /// The plugin checks `/status` and stops the run when asked to.
+    Cancel,
+    /// Something a newer plugin supports that we don't know about.
+    #[serde(other)]
+    Unknown,
+}
+
+const CAPABILITIES: &[Capability] = &[
+    Capability::SuiteResults,
+    Capability::Logs,
+    Capability::Cancel,
+];
+
+impl Capability {
+    fn name(self) -> &'static str {
+        match self {
+            Self::SuiteResults => "streaming test file results",
+            Self::Logs => "forwarding Studio output",
+            Self::Cancel => "cancelling runs",
+            Self::Unknown => "unknown features",
+        }
+    }
+}
+
+/// What happens to a run, sent to whoever asked for it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Event {
+    /// A plugin picked up the run.
+    Started { place: String },
+    /// A plugin that would pick up the run is waiting to be paired.
+    Unpaired { place: String },
+    /// A test file finished running.
+    SuiteResult(SuiteResult),
+    /// Something was printed to the Studio output.
+    Logs(Logs),
+    /// The run finished with these results.
+    Output(Output),
+    /// The test runner errored in Studio.
+    RunError,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuiteResult {
+    pub test_result: TestResult,
+    pub num_total_test_suites: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunRequest {
+    pub options: JestOptions,
+    #[serde(default)]
+    pub place: Option<String>,
+    /// Part of a run split with `--shards`, which is meant to go to several places.
+    #[serde(default)]
+    pub sharded: bool,
+}
+
+impl AppState {
+    pub fn new(config: Arc<Config>, project_root: PathBuf, token: String) -> Self {
+        Self {
+            config,
+            project: Arc::new(project_id(&project_root)),
+            project_root: Arc::new(project_root),
+            token: Arc::new(token),
+            queue: Arc::default(),
+        }
+    }
+
+    /// Queues a run for the next plugin that polls. Dropping the receiver cancels the run if it
+    /// hasn't been picked up yet.
+    pub async fn submit(&self, request: RunRequest) -> mpsc::UnboundedReceiver<Event> {
+        let (events, rx) = mpsc::unbounded_channel();
+        self.queue.lock().await.pending.push_back(Job {
+            options: request.options,
+            place: request.place,
+            sharded: request.sharded,
+            events,
+            capabilities: Vec::new(),
+            suites: Vec::new(),
+            num_total_test_suites: 0,
+        });
+        rx
+    }
+
+    /// Waits for the plugin to stop working on a cancelled run, giving up after `timeout`.
+    pub async fn wait_until_idle(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let cancellable = self
+                .queue
+                .lock()
+                .await
+                .active
+                .values()
+                .any(|job| job.capabilities.contains(&Capability::Cancel));
+
+            // Plugins that can't cancel will keep going however long we wait
+            if !cancellable || Instant::now() >= deadline {
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+/// A hash of the project's full path, so two projects don't get mixed up just because they use the
+/// same port, without telling whoever asks where the project is.
+fn project_id(project_root: &Path) -> String {
+    let path = project_root
+        .canonicalize()
+        .unwrap_or_else(|_| project_root.to_path_buf());
+
+    // FNV-1a, which unlike std's hasher won't change between Rust versions and unpair everyone
+    let hash = path
+        .to_string_lossy()
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{hash:016x}")
+}
+
+pub async fn bind(address: &str) -> anyhow::Result<TcpListener> {
+    let (host, port) = address.rsplit_once(':').unwrap_or((address, ""));
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    // Listening on every interface still includes the one the plugin connects to by default
+    if !["127.0.0.1", "localhost", "0.0.0.0"].contains(&host) {
+        warn!(
+            "The plugin connects to 127.0.0.1 unless it's told otherwise. Set a JestCompanionHost attribute on Workspace to {host} so it can find us."
+        );
+    }
+    if port != DEFAULT_PORT.to_string() {
+        warn!(
+            "The plugin connects to port {DEFAULT_PORT} unless it's told otherwise. Make sure Workspace has a JestCompanionPort attribute set to {port} so it can find us."
+        );
+    }
+
+    match TcpListener::bind(address).await {
+        Ok(listener) => Ok(listener),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => anyhow::bail!(
+            "{address} is already in use, probably by another jest-companion. To run several at once, give each one its own port with `port` in jest-companion.toml or --port."
+        ),
+        Err(e) => Err(anyhow::Error::new(e).context(format!("Failed to listen on {address}"))),
+    }
+}
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/logs", post(logs))
+        .route("/output", post(output))
+        .route("/run", post(run))
+        .route("/run-error", post(run_error))
+        .route("/status", get(status))
+        .route("/suite-result", post(suite_result))
+        .route("/fs/file/{*path}", put(fs_write))
+        .route("/fs/dir/{*path}", put(fs_create_dir_all))
+        .route("/fs/exists/{*path}", get(fs_exists))
+        .route("/fs/file/{*path}", delete(fs_delete))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        // Checks the token itself, so it can tell people how to pair
+        .route("/poll", post(poll))
+        .with_state(state)
+        .layer(DefaultBodyLimit::max(1024 * 1024 * 1024))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PollRequestBody {
+    protocol_version: String,
+    rojo_connected: bool,
+    /// Plugins from before pairing don't send these, but they're turned away before they matter.
+    #[serde(default)]
+    plugin_version: String,
+    /// What the plugin supports.
+    #[serde(default)]
+    capabilities: Vec<Capability>,
+    #[serde(flatten)]
+    place: Place,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PollResponseBody {
+    projects: Vec<String>,
+    options: JestOptions,
+    protocol_version: u32,
+    /// The capabilities the plugin asked for that we'll use.
+    capabilities: Vec<Capability>,
+}
+
+#[derive(Serialize)]
+struct NotPairedBody {
+    project: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusResponseBody {
+    cancel: bool,
+}
+
+async fn require_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !auth::is_authorized(&headers, &state.token) {
+        return (StatusCode::UNAUTHORIZED, "Not paired").into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Requests without one don't belong to any session, so they can't affect a run.
+fn session_id(headers: &HeaderMap) -> String {
+    headers
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+async fn poll(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<PollRequestBody>,
+) -> impl IntoResponse {
+    let session = session_id(&headers);
+    let mut queue = state.queue.lock().await;
+    let queue = &mut *queue;
+
+    if !auth::is_authorized(&headers, &state.token) {
+        // Anyone can send these, so don't let them pile up
+        if queue.unpaired.len() >= MAX_UNPAIRED {
+            queue.unpaired.clear();
+        }
+
+        // Plugins poll constantly, so only say this once
+        if queue.unpaired.insert(session) {
+            warn!(
+                "{} isn't paired with jest-companion yet. Click Pair in the jest-companion plugin and enter {}. If there's no Pair button, update the plugin.",
+                body.place.name(),
+                state.token
+            );
+        }
+
+        // Typing the code in takes a while, so let whoever is waiting for a run know why
+        for job in &queue.pending {
+            if job
+                .place
+                .as_deref()
+                .is_none_or(|target| body.place.matches(target))
+            {
+                job.events
+                    .send(Event::Unpaired {
+                        place: body.place.name().to_string(),
+                    })
+                    .ok();
+            }
+        }
+
+        // The plugin keeps a pairing code for each project, so tell it which one this is
+        let body = NotPairedBody {
+            project: state.project.to_string(),
+        };
+        return (StatusCode::UNAUTHORIZED, Json(body)).into_response();
+    }
+
+    if session.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Missing session").into_response();
+    }
+
+    // Whoever asked for an active run gave up on it, so don't wait on it forever
+    queue.active.retain(|_, job| !job.events.is_closed());
+
+    let now = Instant::now();
+    queue.sessions.insert(
+        session.clone(),
+        Session {
+            place: body.place.clone(),
+            last_seen: now,
+        },
+    );
+    queue.sessions.retain(|id, s| {
+        now.duration_since(s.last_seen) < SESSION_TIMEOUT || queue.active.contains_key(id)
+    });
+
+    if queue.active.contains_key(&session) {
+        warn!("A plugin tried to connect while we are already listening to one.");
+        return (StatusCode::BAD_REQUEST, "Already connected").into_response();
+    }
+
+    // Runs nobody is waiting for anymore don't need to happen
+    queue.pending.retain(|job| !job.events.is_closed());
+
+    let Some(index) = queue.pending.iter().position(|job| {
+        job.place
+            .as_deref()
+            .is_none_or(|target| body.place.matches(target))
+    }) else {
+        return (StatusCode::OK, Json(None::<PollResponseBody>)).into_response();
+    };
+    let mut job = queue.pending.remove(index).unwrap();
+
+    match body.protocol_version.parse::<u32>() {
+        Ok(v) if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&v) => {}
+        _ => {
+            queue.pending.insert(index, job);
+            warn!(
+                "The plugin tried to connect with protocol version {} but we support {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}. Make sure your versions align.",
+                body.protocol_version
+            );
+
+            return (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Unsupported protocol version: expected {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}, got {}",
+                    body.protocol_version
+                ),
+            )
+                .into_response();
+        }
+    };
+
+    let capabilities: Vec<Capability> = CAPABILITIES
+        .iter()
+        .copied()
+        .filter(|c| body.capabilities.contains(c))
+        .collect();
+
+    if capabilities.len() < CAPABILITIES.len() {
+        let missing: Vec<&str> = CAPABILITIES
+            .iter()
+            .filter(|c| !capabilities.contains(c))
+            .map(|c| c.name())
+            .collect();
+        warn!(
+            "The plugin (version {}) doesn't support {}. Update it to use them.",
+            body.plugin_version,
+            missing.join(", ")
+        );
+    }
+
+    if !body.rojo_connected {
+        warn!("Rojo is not connected on the running Studio instance");
+    }
+
+    if job.place.is_none() && !job.sharded && queue.sessions.len() > 1 {
+        let places: Vec<&str> = queue.sessions.values().map(|s| s.place.name()).collect();
+        warn!(
+            "Several places are open in Studio ({}), so tests are running in {}. Use --place to pick one.",
+            places.join(", "),
+            body.place.name()
+        );
+    }
+
+    job.events
+        .send(Event::Started {
+            place: body.place.name().to_string(),
+        })
+        .ok();
+
+    let projects = state.config.projects().to_vec();
+
+    let body = PollResponseBody {
+        projects,
+        options: job.options.clone(),
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: capabilities.clone(),
+    };
+
+    job.capabilities = capabilities;
+    queue.active.insert(session, job);
+
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+async fn run(State(state): State<AppState>, Json(request): Json<RunRequest>) -> impl IntoResponse {
+    let events = state.submit(request).await;
+
+    // Newline-delimited JSON, so the client can handle each event as it arrives
+    let lines = UnboundedReceiverStream::new(events).map(|event| {
+        let mut line = serde_json::to_vec(&event).unwrap();
+        line.push(b'\n');
+        Ok::<_, Infallible>(line)
+    });
+
+    Body::from_stream(lines)
+}
+
+/// Checked by the plugin while it runs tests, so it can stop once nobody wants the results.
+async fn status(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let session = session_id(&headers);
+    let mut queue = state.queue.lock().await;
+
+    let cancel = match queue.active.get(&session) {
+        Some(job) => job.events.is_closed(),
+        None => true,
+    };
+
+    // The plugin stops when told to, so the run is over
+    if cancel {
+        queue.active.remove(&session);
+    }
+
+    Json(StatusResponseBody { cancel })
+}
+
+async fn suite_result(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(result): Json<SuiteResult>,
+) -> impl IntoResponse {
+    let session = session_id(&headers);
+    let mut queue = state.queue.lock().await;
+
+    // Results from a run nobody is waiting for anymore can be dropped
+    let Some(job) = queue.active.get_mut(&session) else {
+        return (StatusCode::OK, ());
+    };
+
+    job.suites.push(result.test_result.clone());
+    job.num_total_test_suites = result.num_total_test_suites;
+    job.events.send(Event::SuiteResult(result)).ok();
+
+    // Once the run has failed enough, end it here instead of waiting for jest-lua to finish. The
+    // plugin stops when it next checks the status.
+    let failed = job.suites.iter().filter(|s| s.failed()).count();
+    if job
+        .options
+        .bail
+        .is_some_and(|bail| bail > 0 && failed >= bail as usize)
+    {
+        let job = queue.active.remove(&session).unwrap();
+
+        // Shards are stopped together by whoever started them
+        if !job.sharded {
+            info!("Stopping the run early because of --bail");
+        }
+
+        let output = Output::interrupted(job.suites, job.num_total_test_suites);
+        job.events.send(Event::Output(output)).ok();
+    }
+
+    (StatusCode::OK, ())
+}
+
+async fn logs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(logs): Json<Logs>,
+) -> impl IntoResponse {
+    if let Some(job) = state.queue.lock().await.active.get(&session_id(&headers)) {
+        job.events.send(Event::Logs(logs)).ok();
+    }
+
+    (StatusCode::OK, ())
+}
+
+async fn output(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(output): Json<Output>,
+) -> impl IntoResponse {
+    let session = session_id(&headers);
+
+    match state.queue.lock().await.active.remove(&session) {
+        Some(job) => {
+            job.events.send(Event::Output(output)).ok();
+        }
+        None => warn!("Received test results, but no run was in progress"),
+    }
+
+    (StatusCode::OK, ())
+}
+
+async fn run_error(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let session = session_id(&headers);
+
+    match state.queue.lock().await.active.remove(&session) {
+        Some(job) => {
+            job.events.send(Event::RunError).ok();
+        }
+        None => warn!("Received a run error, but no run was in progress"),
+    }
+
+    (StatusCode::OK, ())
+}
+
+/// Resolves a path the plugin wants to change, which has to be inside the project and can't be
+/// changed during a `--ci` run.
+async fn writable_path(
+    state: &AppState,
+    headers: &HeaderMap,
+    virtual_path: &str,
+) -> Result<PathBuf, (StatusCode, &'static str)> {
+    let ci = state
+        .queue
+        .lock()
+        .await
+        .active
+        .get(&session_id(headers))
+        .is_some_and(|job| job.options.ci.unwrap_or_default());
+
+    if ci {
+        warn!("Refused to change {virtual_path}, since snapshots are read-only with --ci");
+        return Err((StatusCode::FORBIDDEN, "Snapshots are read-only with --ci"));
+    }
+
+    let Some(real_path) = resolve_path(&state.config, virtual_path, &state.project_root) else {
+        return Err((StatusCode::NOT_FOUND, "Could not resolve path"));
+    };
+
+    if !is_within(&state.project_root, &real_path) {
+        warn!(
+            "Refused to change {}, since it's outside of the project",
+            real_path.display()
+        );
+        return Err((StatusCode::FORBIDDEN, "Path is outside of the project"));
+    }
+
+    Ok(real_path)
+}
+
+async fn fs_write(
+    State(state): State<AppState>,
+    AxumPath(virtual_path): AxumPath<String>,
+    headers: HeaderMap,
+    body: String,
+) -> impl IntoResponse {
+    let real_path = match writable_path(&state, &headers, &virtual_path).await {
+        Ok(real_path) => real_path,
+        Err(response) => return response.into_response(),
+    };
+
+    if let Some(parent) = real_path.parent()
+        && let Err(e) = fs::create_dir_all(parent).await
+    {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    match fs::write(&real_path, body).await {
+        Ok(_) => {
+            info!("File written: {}", real_path.display());
+            (StatusCode::OK, ()).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn fs_create_dir_all(
+    State(state): State<AppState>,
+    AxumPath(virtual_path): AxumPath<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let real_path = match writable_path(&state, &headers, &virtual_path).await {
+        Ok(real_path) => real_path,
+        Err(response) => return response.into_response(),
+    };
+
+    match fs::create_dir_all(&real_path).await {
+        Ok(_) => {
+            info!("Directory created: {}", real_path.display());
+            (StatusCode::OK, ()).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn fs_exists(
+    State(state): State<AppState>,
+    AxumPath(virtual_path): AxumPath<String>,
+) -> impl IntoResponse {
+    match resolve_path(&state.config, &virtual_path, &state.project_root) {
+        Some(real_path) => match fs::metadata(&real_path).await {
+            Ok(_) => (StatusCode::OK, ()).into_response(),
+            Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+        },
+        None => (StatusCode::NOT_FOUND, "Could not resolve path").into_response(),
+    }
+}
+
+async fn fs_delete(
+    State(state): State<AppState>,
+    AxumPath(virtual_path): AxumPath<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let real_path = match writable_path(&state, &headers, &virtual_path).await {
+        Ok(real_path) => real_path,
+        Err(response) => return response.into_response(),
+    };
+
+    match fs::remove_file(&real_path).await {
+        Ok(_) => {
+            info!("File deleted: {}", real_path.display());
+            (StatusCode::OK, ()).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
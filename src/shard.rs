@@ -0,0 +1,226 @@
+use crate::{
+    cache,
+    cli::{JestOptions, RunArgs},
+    config::Config,
+    pattern,
+    resolver::{resolve_path, script_instance_path},
+};
+use anyhow::Context;
+use log::info;
+use regex::Regex;
+use walkdir::WalkDir;
+
+/// Splits a run with these options into one run per shard, each over its own share of the test
+/// files. Without `--shards`, this is just the run itself.
+pub async fn split(
+    args: &RunArgs,
+    options: JestOptions,
+    config: &Config,
+) -> anyhow::Result<Vec<JestOptions>> {
+    let count = args.shards.unwrap_or(1) as usize;
+    if count <= 1 {
+        return Ok(vec![options]);
+    }
+
+    if options.test_match.is_some() {
+        anyhow::bail!("--shards finds test files by itself, so it can't be used with --testMatch");
+    }
+
+    let mut files = test_files(args, config);
+
+    if let Some(Some(pattern)) = &options.test_path_pattern {
+        let regex = Regex::new(pattern).context("Invalid --testPathPattern")?;
+        files.retain(|f| regex.is_match(f));
+    }
+
+    for pattern in options.test_path_ignore_patterns.iter().flatten() {
+        let regex = Regex::new(pattern).context("Invalid --testPathIgnorePatterns")?;
+        files.retain(|f| !regex.is_match(f));
+    }
+
+    // Nothing to split, so let jest-lua report it the way it normally would
+    if files.is_empty() {
+        return Ok(vec![options]);
+    }
+
+    let durations = cache::durations(&args.path).await;
+    let shards = balance(files, count, |f| durations.get(f).copied());
+
+    info!(
+        "Split {} test files into {} shards",
+        shards.iter().map(Vec::len).sum::<usize>(),
+        shards.len()
+    );
+
+    Ok(shards
+        .into_iter()
+        .map(|files| {
+            let mut options = options.clone();
+            options.test_path_pattern = Some(Some(pattern::test_paths(&files)));
+            options
+        })
+        .collect())
+}
+
+/// Finds test files the way jest-lua's default `testMatch` does, returning their instance paths.
+fn test_files(args: &RunArgs, config: &Config) -> Vec<String> {
+    let projects = config.projects();
+    let in_project = |key: &str| {
+        projects.iter().any(|project| {
+            key.strip_prefix(project.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    };
+
+    // Projects can be inside a mapping, and have other mappings inside them
+    let dirs = config
+        .mappings
+        .iter()
+        .filter(|(key, _)| in_project(key))
+        .map(|(_, base)| args.path.join(base))
+        .chain(
+            projects
+                .iter()
+                .filter_map(|project| resolve_path(config, project, &args.path)),
+        );
+
+    let mut files: Vec<String> = dirs
+        .flat_map(WalkDir::new)
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file() && is_module_script(entry.path()))
+        .filter_map(|entry| script_instance_path(config, entry.path(), &args.path))
+        .filter(|path| in_project(path))
+        .filter(|path| is_test_file(path))
+        .collect();
+
+    files.sort();
+    files.dedup();
+    files
+}
+
+fn is_module_script(path: &std::path::Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+
+    (name.ends_with(".luau") || name.ends_with(".lua"))
+        && ![".server.", ".client."]
+            .iter()
+            .any(|kind| name.contains(kind))
+}
+
+/// Matches `**/__tests__/**/*` and `**/?(*.)+(spec|test)`.
+fn is_test_file(instance_path: &str) -> bool {
+    let mut segments: Vec<&str> = instance_path.split('/').collect();
+    let Some(name) = segments.pop() else {
+        return false;
+    };
+
+    segments.contains(&"__tests__")
+        || ["spec", "test"]
+            .iter()
+            .any(|kind| name == *kind || name.ends_with(&format!(".{kind}")))
+}
+
+/// Splits files into at most `count` shards that take about as long as each other, by handing the
+/// slowest remaining file to whichever shard has the least to do. Files we haven't timed before
+/// are assumed to take as long as the average one.
+fn balance(
+    files: Vec<String>,
+    count: usize,
+    duration: impl Fn(&str) -> Option<u64>,
+) -> Vec<Vec<String>> {
+    let known: Vec<u64> = files.iter().filter_map(|f| duration(f)).collect();
+    let average = if known.is_empty() {
+        1
+    } else {
+        (known.iter().sum::<u64>() / known.len() as u64).max(1)
+    };
+
+    let mut files: Vec<(String, u64)> = files
+        .into_iter()
+        .map(|f| {
+            let d = duration(&f).unwrap_or(average);
+            (f, d)
+        })
+        .collect();
+    files.sort_by(|(a, da), (b, db)| db.cmp(da).then_with(|| a.cmp(b)));
+
+    let mut shards: Vec<(u64, Vec<String>)> = vec![(0, Vec::new()); count.min(files.len())];
+
+    for (file, d) in files {
+        let shard = shards.iter_mut().min_by_key(|(total, _)| *total).unwrap();
+        shard.0 += d;
+        shard.1.push(file);
+    }
+
+    shards.into_iter().map(|(_, files)| files).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, path::Path};
+
+    #[test]
+    fn finds_test_files() {
+        for path in [
+            "ReplicatedStorage/Shared/Foo.spec",
+            "ReplicatedStorage/Shared/Foo.test",
+            "ReplicatedStorage/Shared/__tests__/helper",
+            "ReplicatedStorage/Shared/spec",
+        ] {
+            assert!(is_test_file(path), "{path}");
+        }
+
+        for path in [
+            "ReplicatedStorage/Shared/Foo",
+            "ReplicatedStorage/Shared/Foospec",
+            "ReplicatedStorage/__tests__",
+        ] {
+            assert!(!is_test_file(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn skips_scripts() {
+        assert!(is_module_script(Path::new("src/Foo.spec.luau")));
+        assert!(is_module_script(Path::new("src/Foo.lua")));
+        assert!(!is_module_script(Path::new("src/Foo.server.luau")));
+        assert!(!is_module_script(Path::new("src/Foo.client.lua")));
+        assert!(!is_module_script(Path::new("src/Foo.json")));
+    }
+
+    fn files(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn balances_by_duration() {
+        let durations = HashMap::from([("a", 10), ("b", 6), ("c", 5), ("d", 1)]);
+
+        let shards = balance(files(&["a", "b", "c", "d"]), 2, |f| {
+            durations.get(f).copied()
+        });
+
+        assert_eq!(shards, vec![files(&["a", "d"]), files(&["b", "c"])]);
+    }
+
+    #[test]
+    fn assumes_unknown_files_take_the_average() {
+        let durations = HashMap::from([("a", 4), ("b", 2)]);
+
+        // `c` counts as 3, so it goes after `a` but before `b`
+        let shards = balance(files(&["a", "b", "c"]), 3, |f| durations.get(f).copied());
+
+        assert_eq!(shards, vec![files(&["a"]), files(&["c"]), files(&["b"])]);
+    }
+
+    #[test]
+    fn never_makes_empty_shards() {
+        let shards = balance(files(&["a", "b"]), 4, |_| None);
+
+        assert_eq!(shards, vec![files(&["a"]), files(&["b"])]);
+        assert!(balance(Vec::new(), 4, |_| None).is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{config::Config, resolver::resolve_dotted_script};
+use std::{ops::Range, path::Path};
+
+/// A frame in a Roblox stack trace, like `ReplicatedStorage.Shared.Foo.spec:42`.
+#[derive(Debug)]
+pub struct Frame<'a> {
+    /// Where the frame is in the text, covering the path and line number.
+    pub span: Range<usize>,
+    pub instance_path: &'a str,
+    pub line: u32,
+}
+
+pub fn parse(text: &str) -> Vec<Frame<'_>> {
+    let mut frames = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\x1b' => {
+                // Skip color codes, e.g. `\x1b[31m`
+                for (_, c) in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+                start = chars.peek().map_or(text.len(), |(i, _)| *i);
+            }
+            ':' => {
+                let instance_path = &text[start..i];
+                let digits = text[i + 1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(text.len() - i - 1);
+
+                if instance_path.contains('.')
+                    && let Ok(line) = text[i + 1..i + 1 + digits].parse()
+                {
+                    frames.push(Frame {
+                        span: start..i + 1 + digits,
+                        instance_path,
+                        line,
+                    });
+                }
+
+                start = i + 1;
+            }
+            c if c.is_whitespace() || "\"'()[]<>,".contains(c) => start = i + c.len_utf8(),
+            _ => {}
+        }
+    }
+
+    frames
+}
+
+/// Rewrites frames that point at scripts in the project into local `path:line` references.
+pub fn rewrite(text: &str, config: &Config, project_root: &Path) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for frame in parse(text) {
+        if let Some(path) = resolve_dotted_script(config, frame.instance_path, project_root) {
+            out.push_str(&text[last..frame.span.start]);
+            out.push_str(&format!("{}:{}", path.display(), frame.line));
+            last = frame.span.end;
+        }
+    }
+
+    out.push_str(&text[last..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn frames(text: &str) -> Vec<(&str, u32)> {
+        parse(text)
+            .into_iter()
+            .map(|f| (f.instance_path, f.line))
+            .collect()
+    }
+
+    #[test]
+    fn parses_frames() {
+        let text = "ReplicatedStorage.Shared.Foo.spec:42 function adds\nServerScriptService.Bar:7";
+        assert_eq!(
+            frames(text),
+            [
+                ("ReplicatedStorage.Shared.Foo.spec", 42),
+                ("ServerScriptService.Bar", 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_cover_path_and_line() {
+        let text = "at (ReplicatedStorage.Foo:12)";
+        let frame = &parse(text)[0];
+        assert_eq!(&text[frame.span.clone()], "ReplicatedStorage.Foo:12");
+    }
+
+    #[test]
+    fn skips_color_codes() {
+        let text = "\x1b[31mReplicatedStorage.Foo:3\x1b[0m";
+        assert_eq!(frames(text), [("ReplicatedStorage.Foo", 3)]);
+    }
+
+    #[test]
+    fn ignores_things_that_arent_frames() {
+        assert!(frames("Error: expected 1.5 to equal 2").is_empty());
+        assert!(frames("ReplicatedStorage.Foo: no line").is_empty());
+        assert!(frames("Foo:12").is_empty());
+    }
+
+    #[test]
+    fn rewrites_frames_in_the_project() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("src")).unwrap();
+        fs::write(root.path().join("src/Foo.spec.luau"), "").unwrap();
+
+        let config: Config =
+            toml::from_str(r#"mappings = { "ReplicatedStorage/Shared" = "src" }"#).unwrap();
+
+        let text = "ReplicatedStorage.Shared.Foo.spec:42 and ReplicatedStorage.Other:1";
+        let expected = format!(
+            "{}:42 and ReplicatedStorage.Other:1",
+            root.path().join("src/Foo.spec.luau").display()
+        );
+        assert_eq!(rewrite(text, &config, root.path()), expected);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::config::Config;
+use anyhow::Context;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+/// How long to wait for things to settle after a change, so saving several files at once (or Rojo
+/// syncing them into Studio) only triggers one run.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    changes: mpsc::UnboundedReceiver<PathBuf>,
+}
+
+impl FileWatcher {
+    pub fn new(config: &Config, project_root: &Path) -> anyhow::Result<Self> {
+        let (tx, changes) = mpsc::unbounded_channel();
+
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                let Ok(event) = res else {
+                    return;
+                };
+
+                if matches!(event.kind, EventKind::Access(_)) {
+                    return;
+                }
+
+                for path in event.paths {
+                    if !is_snapshot(&path) {
+                        tx.send(path).ok();
+                    }
+                }
+            })?;
+
+        for base in config.mappings.values() {
+            let dir = project_root.join(base);
+
+            // Mappings can point at directories tests create, like snapshot folders
+            if !dir.exists() {
+                continue;
+            }
+
+            watcher
+                .watch(&dir, RecursiveMode::Recursive)
+                .with_context(|| format!("Failed to watch {}", dir.display()))?;
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Waits until a file changes, returning the first path that did.
+    pub async fn changed(&mut self) -> Option<PathBuf> {
+        let first = self.changes.recv().await?;
+
+        while let Ok(Some(_)) = tokio::time::timeout(DEBOUNCE, self.changes.recv()).await {}
+
+        Some(first)
+    }
+}
+
+// Snapshots are written by the test run itself, so they shouldn't trigger another one
+fn is_snapshot(path: &Path) -> bool {
+    path.components().any(|c| c.as_os_str() == "__snapshots__")
+}